@@ -1,12 +1,23 @@
-use crate::nodes::ASTNode;
-use std::collections::BTreeMap;
-use std::fmt::{Debug, Formatter, Write};
+use crate::expand::automatic_variables;
+use crate::fs::{FileSystem, OverlayFileSystem, RealFileSystem};
+use crate::nodes::{format_prerequisites, ASTNode, RuleKind, Target, VariableASTNode};
+use crate::pattern::{match_pattern, match_target_pattern};
+use crate::stream::Location;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Debug, Display, Formatter, Write};
 use std::path::{Path, PathBuf};
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct AST {
     /* Holds a global context... variables defined till now */
     pub context: Context,
     pub nodes: Vec<Box<dyn ASTNode>>,
+    /* Rules of each target in order, including ones nested inside other nodes (eg. in `ifeq` blocks), see AST::push */
+    rules: HashMap<String, Vec<Target>>,
+    /* Pattern rules in order, including nested ones */
+    pattern_rules: Vec<IndexedPatternRule>,
+    /* Files found to be impossible to build in the current AST::find_rules, see AST::can_build */
+    unbuildable: HashSet<String>,
 }
 
 impl Debug for AST {
@@ -19,6 +30,14 @@ impl Debug for AST {
             f.write_str(&format!("\t\t{:?}\n", node))?;
         }
 
+        if !self.diagnostics().is_empty() {
+            f.write_str("\n\tDiagnostics:\n")?;
+
+            for diagnostic in self.diagnostics() {
+                f.write_str(&format!("\t\t{}\n", diagnostic))?;
+            }
+        }
+
         Ok(())
     }
 }
@@ -28,36 +47,590 @@ impl AST {
         AST {
             context: Context::new(root_makefile_parent_dir.to_path_buf()),
             nodes: Vec::new(),
+            rules: HashMap::new(),
+            pattern_rules: Vec::new(),
+            unbuildable: HashSet::new(),
         }
     }
 
     pub fn push(&mut self, node: Box<dyn ASTNode>) {
+        self.index(node.as_ref());
         self.nodes.push(node);
     }
+
+    /* Adds rules in `node` (and nodes nested in it) to the index, so that lookups don't walk all the nodes */
+    fn index(&mut self, node: &dyn ASTNode) {
+        if let Some(target) = node.as_target() {
+            self.rules
+                .entry(target.target_name.clone())
+                .or_default()
+                .push(target.clone());
+        }
+
+        if let Some(rule) = node.as_pattern_rule() {
+            self.pattern_rules.push(IndexedPatternRule {
+                targets: rule.targets.clone(),
+                pattern: format!(
+                    "{}{} {}",
+                    rule.targets.join(" "),
+                    if rule.terminal { "::" } else { ":" },
+                    format_prerequisites(&rule.normal_deps, &rule.order_only_deps)
+                ),
+                normal_deps: rule.normal_deps.clone(),
+                order_only_deps: rule.order_only_deps.clone(),
+                terminal: rule.terminal,
+                recipe: rule.recipe_lines(),
+                defined_in: rule.defined_in.clone(),
+            });
+        }
+
+        /* Targets can be nested in a target too, ie. in a conditional read as part of its recipe */
+        for child in node.children() {
+            self.index(child);
+        }
+    }
+
+    /* Messages from `$(error)`, `$(warning)` and `$(info)` calls expanded till now, in order */
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.context.diagnostics
+    }
+
+    /* Expands each of `words` and splits the results into words, eg. prerequisites like `$(OBJS)` */
+    fn expand_words(&mut self, words: &[String]) -> Vec<String> {
+        words
+            .iter()
+            .flat_map(|word| {
+                self.context
+                    .expand(word)
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /**
+     * Rules make would use to build `file`, ie. an explicit rule with a recipe, else the first pattern rule
+     * (preferring shorter stems) whose prerequisites exist or can be built, same as make
+     *
+     * @note Explicit rule without a recipe is returned only if no pattern rule matches
+     * @note Each double-colon rule of `file` is a rule of its own, in the order they were defined (same as make
+     * runs their recipes), so more than one rule is returned only for double-colon rules
+     */
+    pub fn find_rules(&mut self, file: &str) -> Vec<RuleMatch> {
+        self.unbuildable.clear();
+
+        let explicit = self.explicit_rules(file);
+
+        if explicit.is_empty() {
+            return self
+                .find_pattern_rule(file, &mut Vec::new())
+                .into_iter()
+                .collect();
+        }
+
+        explicit
+            .into_iter()
+            .map(|rule| self.match_explicit_rule(file, rule))
+            .collect()
+    }
+
+    /**
+     * Explicit rules of `file`, ie. all its rules merged into one (same as make), or each of its double-colon rules
+     *
+     * @note Same as make, if more than one rule has a recipe, the last one is used, and its prerequisites come first
+     */
+    fn explicit_rules(&self, file: &str) -> Vec<Target> {
+        let rules: &[Target] = self.rules.get(file).map_or(&[], |rules| rules);
+
+        /* Same as make, the first rule decides whether all of them are double-colon rules */
+        let kind = match rules.first() {
+            Some(rule) => rule.kind,
+            None => return Vec::new(),
+        };
+        let mut rules = rules.iter().filter(|rule| rule.kind == kind);
+
+        if kind == RuleKind::DoubleColon {
+            return rules.cloned().collect();
+        }
+
+        /* SAFETY: There is at least one rule, the first one */
+        let mut merged = rules.next().unwrap().clone();
+
+        for rule in rules {
+            if rule.recipe_lines().is_empty() {
+                merged.normal_deps.extend_from_slice(&rule.normal_deps);
+                merged
+                    .order_only_deps
+                    .extend_from_slice(&rule.order_only_deps);
+            } else {
+                merged = Target {
+                    normal_deps: [rule.normal_deps.as_slice(), &merged.normal_deps].concat(),
+                    order_only_deps: [rule.order_only_deps.as_slice(), &merged.order_only_deps]
+                        .concat(),
+                    ..rule.clone()
+                };
+            }
+        }
+
+        vec![merged]
+    }
+
+    /* The explicit `rule` of `file`, or if it has no recipe, the pattern rule that would be used instead */
+    fn match_explicit_rule(&mut self, file: &str, rule: Target) -> RuleMatch {
+        let normal_deps = self.expand_words(&rule.normal_deps);
+        let order_only_deps = self.expand_words(&rule.order_only_deps);
+        let recipe = rule.recipe_lines();
+
+        if !recipe.is_empty() {
+            return RuleMatch {
+                pattern: None,
+                stem: rule.stem,
+                normal_deps,
+                order_only_deps,
+                recipe,
+                defined_in: rule.defined_in,
+                group: rule.group,
+            };
+        }
+
+        match self.find_pattern_rule(file, &mut Vec::new()) {
+            Some(mut pattern_rule) => {
+                /* Prerequisites of an explicit rule without recipe are prerequisites of the pattern rule used */
+                pattern_rule.normal_deps.extend(normal_deps);
+                pattern_rule.order_only_deps.extend(order_only_deps);
+
+                pattern_rule
+            }
+            None => RuleMatch {
+                pattern: None,
+                stem: rule.stem,
+                normal_deps,
+                order_only_deps,
+                recipe,
+                defined_in: rule.defined_in,
+                group: rule.group,
+            },
+        }
+    }
+
+    /**
+     * @param `in_use` Indices of pattern rules already used in the chain leading to `file`, same as make a rule is
+     * not used twice in a chain (eg. `%: %.o` wouldn't be used to build `foo.o.o`)
+     *
+     * @note Same as make, a match-anything target (ie. `%`) of a rule that isn't terminal is not used for an
+     * intermediate file, ie. a prerequisite in the chain, and a terminal rule is used only if its prerequisites exist
+     */
+    fn find_pattern_rule(&mut self, file: &str, in_use: &mut Vec<usize>) -> Option<RuleMatch> {
+        let intermediate = !in_use.is_empty();
+
+        /* Index of the rule, along with directory and stem, see match_target_pattern */
+        let mut candidates = Vec::new();
+        for (i, rule) in self.pattern_rules.iter().enumerate() {
+            /* A pattern rule without recipe only cancels rules, it can't build anything */
+            if rule.recipe.is_empty() || in_use.contains(&i) {
+                continue;
+            }
+
+            if let Some((dir, stem)) = rule
+                .targets
+                .iter()
+                .filter(|pattern| rule.terminal || !intermediate || pattern.as_str() != "%")
+                .find_map(|pattern| match_target_pattern(pattern, file))
+            {
+                candidates.push((i, dir.to_string(), stem.to_string()));
+            }
+        }
+
+        candidates.sort_by_key(|(_, dir, stem)| dir.len() + stem.len());
+
+        for (i, dir, stem) in candidates {
+            let IndexedPatternRule {
+                pattern,
+                normal_deps,
+                order_only_deps,
+                terminal,
+                recipe,
+                defined_in,
+                ..
+            } = self.pattern_rules[i].clone();
+
+            let mut substitute = |deps: &[String]| -> Vec<String> {
+                self.expand_words(deps)
+                    .into_iter()
+                    .map(|dep| match dep.contains('%') {
+                        true => dir.clone() + &dep.replacen('%', &stem, 1),
+                        false => dep,
+                    })
+                    .collect()
+            };
+            let normal_deps = substitute(&normal_deps);
+            let order_only_deps = substitute(&order_only_deps);
+
+            in_use.push(i);
+            let mut buildable = true;
+            for dep in normal_deps.iter().chain(&order_only_deps) {
+                let found = match terminal {
+                    true => self.exists(dep),
+                    false => self.can_build(dep, in_use),
+                };
+
+                if !found {
+                    buildable = false;
+                    break;
+                }
+            }
+            in_use.pop();
+
+            if buildable {
+                return Some(RuleMatch {
+                    pattern: Some(pattern),
+                    stem: Some(dir + &stem),
+                    normal_deps,
+                    order_only_deps,
+                    recipe,
+                    defined_in,
+                    group: Vec::new(),
+                });
+            }
+        }
+
+        None
+    }
+
+    fn exists(&self, file: &str) -> bool {
+        self.context
+            .fs
+            .exists(&self.context.root_makefile_dir.join(file))
+    }
+
+    /**
+     * Whether `file` exists, is a target of an explicit rule, or can be built with pattern rules not `in_use`
+     *
+     * @note A file which can't be built isn't tried again in the same AST::find_rules, even in another chain
+     */
+    fn can_build(&mut self, file: &str, in_use: &mut Vec<usize>) -> bool {
+        if self.unbuildable.contains(file) {
+            return false;
+        }
+
+        let buildable = self.exists(file)
+            || self.rules.contains_key(file)
+            || self.find_pattern_rule(file, in_use).is_some();
+
+        if !buildable {
+            self.unbuildable.insert(file.to_string());
+        }
+
+        buildable
+    }
+
+    /**
+     * Expands recipes of `goals` and their prerequisites (recursively), in the order make would run them
+     *
+     * Target-specific variables of a target are also in effect for its prerequisites, except `private` ones
+     *
+     * @note Same as make, a target is made once per run, even if more than one goal needs it
+     * @returns Pairs of target name and its expanded recipe lines
+     */
+    pub fn expand_recipes(&mut self, goals: &[String]) -> Vec<(String, Vec<String>)> {
+        let mut recipes = Vec::new();
+        let mut visited = Vec::new();
+
+        for goal in goals {
+            self.expand_recipes_of(goal, &mut recipes, &mut visited);
+        }
+
+        recipes
+    }
+
+    fn expand_recipes_of(
+        &mut self,
+        target_name: &str,
+        recipes: &mut Vec<(String, Vec<String>)>,
+        visited: &mut Vec<String>,
+    ) {
+        if visited.iter().any(|t| t == target_name) {
+            return;
+        }
+        visited.push(target_name.to_string());
+
+        /* A file without a rule has nothing to run, each double-colon rule of a target runs its own recipe */
+        for rule in self.find_rules(target_name) {
+            self.context.push_target_scope(target_name, false);
+            for dep in rule.normal_deps.iter().chain(&rule.order_only_deps) {
+                self.expand_recipes_of(dep, recipes, visited);
+            }
+            self.context.pop_target_scope();
+
+            self.context.push_target_scope(target_name, true);
+            let automatic = automatic_variables(
+                &self.context,
+                target_name,
+                &rule.normal_deps,
+                &rule.order_only_deps,
+                rule.stem.as_deref(),
+            );
+            self.context.push_scope(automatic);
+
+            let lines = rule
+                .recipe
+                .iter()
+                .map(|line| self.context.expand(line))
+                .collect();
+            self.context.pop_scope();
+            self.context.pop_target_scope();
+
+            recipes.push((target_name.to_string(), lines));
+
+            /* Recipe of a grouped rule makes all its targets, so it isn't run again for the others */
+            for target in rule.group {
+                if !visited.contains(&target) {
+                    visited.push(target);
+                }
+            }
+        }
+    }
+}
+
+/* Pattern rule as used to find rules, see AST::index */
+#[derive(Clone)]
+struct IndexedPatternRule {
+    targets: Vec<String>,
+    /* As written, eg. `%.o: %.c` */
+    pattern: String,
+    normal_deps: Vec<String>,
+    order_only_deps: Vec<String>,
+    terminal: bool,
+    /* Recipe lines as written, ie. unexpanded */
+    recipe: Vec<String>,
+    defined_in: PathBuf,
+}
+
+/* Rule that would build a file, see AST::find_rules */
+pub struct RuleMatch {
+    /* Pattern rule as written, eg. `%.o: %.c`, None for an explicit rule */
+    pub pattern: Option<String>,
+    /* Stem of a pattern rule, or of an explicit rule made by a static pattern rule */
+    pub stem: Option<String>,
+    /* Expanded prerequisites, for a pattern rule these have the stem substituted */
+    pub normal_deps: Vec<String>,
+    pub order_only_deps: Vec<String>,
+    /* Recipe lines as written, ie. unexpanded */
+    pub recipe: Vec<String>,
+    pub defined_in: PathBuf,
+    /* Targets made along with the file by a single run of the recipe, see Target::group */
+    pub group: Vec<String>,
+}
+
+/* Where a variable's current value came from, as reported by `$(origin)` */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Origin {
+    Default,
+    Environment,
+    EnvironmentOverride,
+    File,
+    CommandLine,
+    Override,
+    Automatic,
+}
+
+impl Origin {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Origin::Default => "default",
+            Origin::Environment => "environment",
+            Origin::EnvironmentOverride => "environment override",
+            Origin::File => "file",
+            Origin::CommandLine => "command line",
+            Origin::Override => "override",
+            Origin::Automatic => "automatic",
+        }
+    }
+
+    /* Whether an assignment from `self` can replace a value that came from `existing` */
+    fn can_replace(self, existing: Origin) -> bool {
+        match existing {
+            Origin::Override => self == Origin::Override,
+            Origin::CommandLine => matches!(self, Origin::Override | Origin::CommandLine),
+            Origin::EnvironmentOverride => matches!(
+                self,
+                Origin::Override | Origin::CommandLine | Origin::EnvironmentOverride
+            ),
+            _ => true,
+        }
+    }
+}
+
+/* @ref: https://www.gnu.org/software/make/manual/html_node/Flavors.html */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Flavor {
+    /* `=`, expanded every time it is referenced */
+    Recursive,
+    /* `:=`, expanded once at the time of assignment, and then stored verbatim */
+    Simple,
+}
+
+impl Flavor {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Flavor::Recursive => "recursive",
+            Flavor::Simple => "simple",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Variable {
+    pub value: String,
+    pub flavor: Flavor,
+    pub origin: Origin,
+    pub exported: bool,
+    /* `private` variables are not inherited by prerequisites */
+    pub private: bool,
+}
+
+impl Variable {
+    pub fn new(value: String, flavor: Flavor, origin: Origin) -> Self {
+        Variable {
+            value,
+            flavor,
+            origin,
+            exported: false,
+            private: false,
+        }
+    }
+}
+
+/* A single assignment to a variable, as written in the makefile */
+#[derive(Clone, Debug)]
+pub struct Assignment {
+    pub location: Location,
+    pub operator: String,
+    /* Unexpanded value */
+    pub value: String,
+    pub origin: Origin,
+    /* Conditionals this assignment is nested in, outermost first, eg. ["ifeq ($(ARCH),x86_64)", "else of ifdef DEBUG"] */
+    pub conditionals: Vec<String>,
+    /* Set for target-specific assignments, to the targets (or pattern) it applies to */
+    pub target: Option<String>,
+    /* false if the assignment was ignored, eg. normal assignment to a variable given on command line */
+    pub applied: bool,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Info,
+    Warning,
+    /* Aborts evaluation of the makefile, see Context::aborted */
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/* Message of an `$(error)`, `$(warning)` or `$(info)` call */
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /* Location of the line whose expansion called the function */
+    pub location: Location,
+    /* Expanded message */
+    pub message: String,
+    /* Conditionals the call is nested in, outermost first, same as Assignment::conditionals */
+    pub conditionals: Vec<String>,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{}: {}: {}",
+            self.location,
+            self.severity.as_str(),
+            self.message
+        )?;
+
+        if !self.conditionals.is_empty() {
+            write!(f, "\t[in {}]", self.conditionals.join(" > "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/* Variables make defines by default, which makefiles commonly rely on */
+const DEFAULT_VARIABLES: [(&str, &str); 10] = [
+    ("AR", "ar"),
+    ("ARFLAGS", "rv"),
+    ("AS", "as"),
+    ("CC", "cc"),
+    ("CPP", "$(CC) -E"),
+    ("CXX", "g++"),
+    ("LD", "ld"),
+    ("MAKE", "make"),
+    ("RM", "rm -f"),
+    ("SHELL", "/bin/sh"),
+];
+
 pub struct Context {
-    modifiables: Vec<String>,
-    simple_expanded: Vec<String>,
-    mapping: BTreeMap<String, String>,
+    variables: BTreeMap<String, Variable>,
+    /* Target-specific variables, keyed by the target name (or pattern, for pattern-specific variables) in order of appearance */
+    target_variables: BTreeMap<String, Vec<VariableASTNode>>,
+    /* Layers of variables shadowing `variables`, eg. target-specific variables while expanding a recipe */
+    scopes: Vec<BTreeMap<String, Variable>>,
+    /* Number of `scopes` pushed for targets, see Context::push_target_scope */
+    target_scopes: usize,
+    /* Every assignment to each variable, in order */
+    history: BTreeMap<String, Vec<Assignment>>,
+    /* Location of the line being handled, updated by whoever reads lines from the Stream */
+    pub location: Location,
+    /* Conditionals enclosing the line being handled, outermost first */
+    pub conditionals: Vec<String>,
+    /*
+     * Filesystem used by functions like `$(wildcard)` and `$(file)`, relative paths are relative to root_makefile_dir
+     * By default writes are kept in memory, see OverlayFileSystem
+     */
+    pub fs: Box<dyn FileSystem>,
     pub root_makefile_dir: PathBuf,
+    /* Text generated by `$(eval)`s, along with where they were called, to be parsed after the line being handled */
+    evals: Vec<(String, Location)>,
+    /* See AST::diagnostics */
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl Debug for Context {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         f.write_str("\tContext: \n")?;
-        for (k, v) in &self.mapping {
+        for (k, v) in &self.variables {
+            if v.origin == Origin::Default {
+                continue;
+            }
+
+            let mut attributes = String::new();
+            for (is_set, attribute) in [
+                (v.origin == Origin::Override, " [override]"),
+                (v.private, " [private]"),
+                (v.exported, " [export]"),
+            ] {
+                if is_set {
+                    attributes += attribute;
+                }
+            }
+
             f.write_str(&format!(
-                "\t\t{}\t{}: {}\n",
+                "\t\t{}{}\t{}: {}\n",
                 k,
-                if self.modifiables.contains(&k) {
-                    '?'
-                } else if self.simple_expanded.contains(&k) {
-                    ':'
-                } else {
-                    ' '
-                },
-                v
+                attributes,
+                if v.flavor == Flavor::Simple { ':' } else { ' ' },
+                v.value
             ))?;
         }
 
@@ -67,55 +640,365 @@ impl Debug for Context {
 
 impl Context {
     pub fn new(root_makefile_dir: PathBuf) -> Self {
+        let mut variables = BTreeMap::new();
+
+        for (name, value) in DEFAULT_VARIABLES {
+            variables.insert(
+                name.to_string(),
+                Variable::new(value.to_string(), Flavor::Recursive, Origin::Default),
+            );
+        }
+
         Context {
             root_makefile_dir,
-            modifiables: Vec::new(),
-            simple_expanded: Vec::new(),
-            mapping: BTreeMap::new(),
+            variables,
+            target_variables: BTreeMap::new(),
+            scopes: Vec::new(),
+            target_scopes: 0,
+            history: BTreeMap::new(),
+            location: Location::default(),
+            conditionals: Vec::new(),
+            fs: Box::new(OverlayFileSystem::new(Box::new(RealFileSystem {}))),
+            evals: Vec::new(),
+            diagnostics: Vec::new(),
         }
     }
 
-    pub fn get(&self, var_name: &str) -> Option<&String> {
-        self.mapping.get(var_name)
+    /* Records a diagnostic for the line being handled, an `Error` aborts evaluation (see Context::aborted) */
+    pub fn diagnose(&mut self, severity: Severity, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            location: self.location.clone(),
+            message,
+            conditionals: self.conditionals.clone(),
+        });
     }
 
     /**
-     * @note If the key was already present, then this call will 'update' the
-     * value, previous value is lost
+     * The `$(error)` which aborted evaluation, same as make stopping there
+     *
+     * @note Once aborted, expansions are empty and no more lines should be handled
+     */
+    pub fn aborted(&self) -> Option<&Diagnostic> {
+        self.diagnostics
+            .iter()
+            .find(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    /* Queues `text` to be parsed as makefile lines, after the line being handled */
+    pub fn eval(&mut self, text: String) {
+        self.evals.push((text, self.location.clone()));
+    }
+
+    /* Texts queued by Context::eval so far, in order, along with where each was queued */
+    pub fn take_evals(&mut self) -> Vec<(String, Location)> {
+        std::mem::take(&mut self.evals)
+    }
+
+    /* Assignments made to `var_name` so far, in order */
+    pub fn history(&self, var_name: &str) -> &[Assignment] {
+        self.history
+            .get(var_name)
+            .map(|assignments| assignments.as_slice())
+            .unwrap_or_default()
+    }
+
+    fn record(
+        &mut self,
+        var_name: &str,
+        operator: &str,
+        value: &str,
+        origin: Origin,
+        target: Option<&str>,
+        applied: bool,
+    ) {
+        let assignment = Assignment {
+            location: self.location.clone(),
+            operator: operator.to_string(),
+            value: value.to_string(),
+            origin,
+            conditionals: self.conditionals.clone(),
+            target: target.map(String::from),
+            applied,
+        };
+
+        self.history
+            .entry(var_name.to_string())
+            .or_default()
+            .push(assignment);
+    }
+
+    /**
+     * Seeds variables from the process environment, with `overrides` they also take precedence
+     * over assignments in makefiles (same as `make -e`)
+     */
+    pub fn import_environment(&mut self, overrides: bool) {
+        let origin = if overrides {
+            Origin::EnvironmentOverride
+        } else {
+            Origin::Environment
+        };
+
+        for (name, value) in std::env::vars() {
+            /* make never takes SHELL from the environment */
+            if name == "SHELL" {
+                continue;
+            }
+
+            let mut variable = Variable::new(value, Flavor::Recursive, origin);
+            variable.exported = true;
+
+            self.variables.insert(name, variable);
+        }
+    }
+
+    pub fn get(&self, var_name: &str) -> Option<&Variable> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(var_name))
+            .or_else(|| {
+                /* Same as make, a global `private` variable is not inherited by any target */
+                self.variables
+                    .get(var_name)
+                    .filter(|variable| self.target_scopes == 0 || !variable.private)
+            })
+    }
+
+    /* All global variables, sorted by name */
+    pub fn variables(&self) -> impl Iterator<Item = (&String, &Variable)> {
+        self.variables.iter()
+    }
+
+    pub fn push_scope(&mut self, scope: BTreeMap<String, Variable>) {
+        self.scopes.push(scope);
+    }
+
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /**
+     * Pushes the target-specific and pattern-specific variables of `target` as a scope, see Context::target_scope
+     *
+     * @note Till it is popped with Context::pop_target_scope, global `private` variables are hidden
+     */
+    pub fn push_target_scope(&mut self, target: &str, include_private: bool) {
+        /* Hidden while building the scope too, eg. `P += x` for the target doesn't append to a private global P */
+        self.target_scopes += 1;
+        let scope = self.target_scope(target, include_private);
+
+        self.push_scope(scope);
+    }
+
+    pub fn pop_target_scope(&mut self) {
+        self.pop_scope();
+        self.target_scopes -= 1;
+    }
+
+    /**
+     * Records a target-specific variable, `target` can also be a pattern such as `%.o`
+     */
+    pub fn set_target_variable(&mut self, target: &str, variable: VariableASTNode) {
+        let origin = if variable.is_override {
+            Origin::Override
+        } else {
+            Origin::File
+        };
+        self.record(
+            &variable.name,
+            &variable.operator,
+            &variable.value,
+            origin,
+            Some(target),
+            true,
+        );
+
+        self.target_variables
+            .entry(target.to_string())
+            .or_default()
+            .push(variable);
+    }
+
+    /**
+     * Builds the layer of target-specific and pattern-specific variables applying to `target`
+     *
+     * @note Pattern-specific variables are applied first, so target-specific ones take precedence
      */
-    pub fn set(&mut self, mut var_name: String, mut new_value: String) {
-        if var_name.ends_with('?') {
-            /* remove '?' from name */
-            var_name.pop();
+    fn target_scope(&mut self, target: &str, include_private: bool) -> BTreeMap<String, Variable> {
+        let mut variables: Vec<VariableASTNode> = Vec::new();
 
-            self.modifiables.push(var_name.clone());
+        for (pattern, vars) in &self.target_variables {
+            if pattern.contains('%') && match_pattern(pattern, target).is_some() {
+                variables.extend(vars.iter().cloned());
+            }
+        }
+
+        if let Some(vars) = self.target_variables.get(target) {
+            variables.extend(vars.iter().cloned());
         }
 
-        /* @ref: https://www.gnu.org/software/make/manual/html_node/Flavors.html */
-        if var_name.ends_with(':') {
-            /* remove ':' or '::' from name */
-            var_name = var_name.trim_end_matches(':').to_string();
+        let mut scope: BTreeMap<String, Variable> = BTreeMap::new();
+
+        for variable in variables {
+            if variable.is_private && !include_private {
+                continue;
+            }
+
+            let origin = if variable.is_override {
+                Origin::Override
+            } else {
+                Origin::File
+            };
+
+            /* Value visible before this assignment */
+            let previous = scope
+                .get(&variable.name)
+                .or_else(|| self.get(&variable.name))
+                .cloned();
 
-            self.simple_expanded.push(var_name.clone());
+            /* Same as make, only command line (and environment, with -e) values beat target-specific ones */
+            if let Some(previous) = &previous {
+                if matches!(
+                    previous.origin,
+                    Origin::CommandLine | Origin::EnvironmentOverride
+                ) && !origin.can_replace(previous.origin)
+                {
+                    continue;
+                }
+            }
+
+            let new_variable = match (variable.operator.as_str(), previous) {
+                ("+=", Some(mut previous)) => {
+                    let value = match previous.flavor {
+                        Flavor::Simple => self.expand(&variable.value),
+                        Flavor::Recursive => variable.value,
+                    };
+
+                    previous.value = previous.value + " " + &value;
+                    previous.origin = origin;
+                    previous
+                }
+                ("?=", Some(previous)) => previous,
+                (":=" | "::=" | ":::=", _) => {
+                    Variable::new(self.expand(&variable.value), Flavor::Simple, origin)
+                }
+                _ => Variable::new(variable.value, Flavor::Recursive, origin),
+            };
+
+            scope.insert(variable.name, new_variable);
         }
 
-        if var_name.ends_with('+') {
-            /* remove '+' from name */
-            var_name.pop();
+        scope
+    }
 
-            /* modify new_value by adding it to previous value if any exists */
-            if let Some(old_value) = self.get(&var_name) {
-                new_value = old_value.clone() + " " + &new_value;
+    /**
+     * Assigns `new_value` to the variable as per `operator` (ie. `=`, `:=`, `+=`, `?=` etc.)
+     *
+     * @note If the variable was already present, then this call will 'update' the
+     * value, previous value is lost
+     *
+     * @note Assignments from a lower precedence origin are ignored, eg. a normal assignment
+     * to a variable previously set with `override`
+     */
+    pub fn set(&mut self, var_name: &str, operator: &str, mut new_value: String, origin: Origin) {
+        let existing = self.variables.get(var_name).cloned();
+
+        let applied = match &existing {
+            Some(existing) => {
+                /* `?=` only assigns if not already defined */
+                origin.can_replace(existing.origin) && operator != "?="
+            }
+            None => true,
+        };
+
+        self.record(var_name, operator, &new_value, origin, None, applied);
+
+        if !applied {
+            return;
+        }
+
+        let flavor = match (operator, existing) {
+            (":=" | "::=" | ":::=", _) => {
+                new_value = self.expand(&new_value);
+                Flavor::Simple
             }
+            ("+=", Some(existing)) => {
+                let flavor = existing.flavor;
+                let old_value = existing.value;
+
+                /* appending to a simply expanded variable, expands the new part immediately */
+                if flavor == Flavor::Simple {
+                    new_value = self.expand(&new_value);
+                }
+
+                new_value = old_value + " " + &new_value;
+                flavor
+            }
+            ("!=", _) => {
+                println!(
+                    "❗ Not running shell for '{} != {}', storing the command as its value",
+                    var_name, new_value
+                );
+                Flavor::Recursive
+            }
+            _ => Flavor::Recursive,
+        };
+
+        let mut variable = Variable::new(new_value, flavor, origin);
+
+        if let Some(existing) = self.variables.get(var_name) {
+            variable.exported = existing.exported;
+            variable.private = existing.private;
+        }
+
+        self.variables.insert(var_name.to_string(), variable);
+    }
+
+    /**
+     * Removes the variable completely, as if it was never defined
+     *
+     * @note If not present, or it came from a higher precedence origin (eg. `override`) than the
+     * undefine itself, then this call is simply a no-op
+     */
+    pub fn undefine(&mut self, var_name: &str, is_override: bool) {
+        let origin = if is_override {
+            Origin::Override
+        } else {
+            Origin::File
+        };
+
+        let applied = match self.variables.get(var_name) {
+            Some(existing) => origin.can_replace(existing.origin),
+            None => false,
+        };
+
+        self.record(var_name, "undefine", "", origin, None, applied);
+
+        if applied {
+            self.variables.remove(var_name);
         }
+    }
 
-        self.mapping.insert(var_name, new_value);
+    /* @note Exporting a variable which is not defined yet is a no-op */
+    pub fn export(&mut self, var_name: &str) {
+        if let Some(variable) = self.variables.get_mut(var_name) {
+            variable.exported = true;
+        }
     }
 
     /**
-     * @note If not present, then this call is simply a no-op
+     * @note Unlike Context::undefine, the variable keeps its value, it is just not exported anymore
      */
-    pub fn unset(&mut self, var_name: &str) {
-        self.mapping.remove(var_name);
+    pub fn unexport(&mut self, var_name: &str) {
+        if let Some(variable) = self.variables.get_mut(var_name) {
+            variable.exported = false;
+        }
+    }
+
+    pub fn make_private(&mut self, var_name: &str) {
+        if let Some(variable) = self.variables.get_mut(var_name) {
+            variable.private = true;
+        }
     }
 }
@@ -0,0 +1,227 @@
+use super::{split_arguments, Expander};
+use crate::ast::{Flavor, Origin, Variable};
+use std::collections::BTreeMap;
+
+/* Variable bound by `foreach`, `let` and `call` while expanding their body */
+fn bound(value: &str) -> Variable {
+    Variable::new(value.to_string(), Flavor::Simple, Origin::Automatic)
+}
+
+impl Expander<'_> {
+    /**
+     * Conditional and other functions controlling expansion, ie. `if`, `or`, `and`, `intcmp`,
+     * `foreach`, `let`, `call` and `value`
+     *
+     * @note Arguments are only expanded when needed, eg. only the chosen branch of `if` is expanded
+     *
+     * @ref: https://www.gnu.org/software/make/manual/html_node/Conditional-Functions.html
+     */
+    pub(super) fn control_function(&mut self, function: &str, arguments: &str) -> String {
+        match function {
+            "if" => {
+                let args = split_arguments(arguments, 3);
+                if args.len() < 2 {
+                    return self.insufficient_arguments(function, args.len());
+                }
+
+                /* Condition is stripped, so one consisting only of whitespace is false */
+                if !self.expand(args[0]).trim().is_empty() {
+                    self.expand(args[1])
+                } else {
+                    args.get(2).map(|e| self.expand(e)).unwrap_or_default()
+                }
+            }
+            "or" => {
+                for arg in split_arguments(arguments, usize::MAX) {
+                    let value = self.expand(arg);
+
+                    if !value.trim().is_empty() {
+                        return value;
+                    }
+                }
+
+                String::new()
+            }
+            "and" => {
+                let mut value = String::new();
+
+                for arg in split_arguments(arguments, usize::MAX) {
+                    value = self.expand(arg);
+
+                    if value.trim().is_empty() {
+                        return String::new();
+                    }
+                }
+
+                value
+            }
+            "intcmp" => {
+                let args = split_arguments(arguments, 5);
+                if args.len() < 2 {
+                    return self.insufficient_arguments(function, args.len());
+                }
+
+                let mut numbers = Vec::new();
+                for arg in &args[..2] {
+                    let expanded = self.expand(arg);
+
+                    match expanded.trim().parse::<i128>() {
+                        Ok(number) => numbers.push(number),
+                        Err(_) => {
+                            println!(
+                                "❗ Non-numeric argument '{}' given to function 'intcmp'",
+                                expanded.trim()
+                            );
+                            return String::new();
+                        }
+                    }
+                }
+
+                let (lhs, rhs) = (numbers[0], numbers[1]);
+
+                if args.len() == 2 {
+                    /* Without any branches, result is the number itself if both are equal */
+                    return if lhs == rhs {
+                        lhs.to_string()
+                    } else {
+                        String::new()
+                    };
+                }
+
+                /* A missing gt-part defaults to the eq-part */
+                let branch = match lhs.cmp(&rhs) {
+                    std::cmp::Ordering::Less => args.get(2),
+                    std::cmp::Ordering::Equal => args.get(3),
+                    std::cmp::Ordering::Greater => args.get(4).or(args.get(3)),
+                };
+
+                branch.map(|b| self.expand(b)).unwrap_or_default()
+            }
+            "foreach" => {
+                let args = split_arguments(arguments, 3);
+                if args.len() < 3 {
+                    return self.insufficient_arguments(function, args.len());
+                }
+
+                let name = self.expand(args[0]).trim().to_string();
+                let list = self.expand(args[1]);
+
+                let mut results = Vec::new();
+                for word in list.split_whitespace() {
+                    self.context
+                        .push_scope(BTreeMap::from([(name.clone(), bound(word))]));
+                    results.push(self.expand(args[2]));
+                    self.context.pop_scope();
+                }
+
+                results.join(" ")
+            }
+            "let" => {
+                let args = split_arguments(arguments, 3);
+                if args.len() < 3 {
+                    return self.insufficient_arguments(function, args.len());
+                }
+
+                let names = self.expand(args[0]);
+                let names: Vec<&str> = names.split_whitespace().collect();
+                let list = self.expand(args[1]);
+                let mut words = list.split_whitespace();
+
+                /* Last variable gets all the remaining words */
+                let mut scope = BTreeMap::new();
+                for (i, name) in names.iter().enumerate() {
+                    let value = if i + 1 == names.len() {
+                        words.by_ref().collect::<Vec<_>>().join(" ")
+                    } else {
+                        words.next().unwrap_or_default().to_string()
+                    };
+
+                    scope.insert(name.to_string(), bound(&value));
+                }
+
+                self.context.push_scope(scope);
+                let result = self.expand(args[2]);
+                self.context.pop_scope();
+
+                result
+            }
+            "call" => {
+                let args = split_arguments(arguments, usize::MAX);
+                let name = self.expand(args[0]).trim().to_string();
+                let params: Vec<String> = args[1..].iter().map(|p| self.expand(p)).collect();
+
+                if let Some(result) = self.call_builtin(&name, &params) {
+                    return result;
+                }
+
+                /* Parameters not given are empty, even if an enclosing `call` had them */
+                let mut scope = BTreeMap::from([("0".to_string(), bound(&name))]);
+                for i in 1..=params.len().max(9) {
+                    let param = params.get(i - 1).map(String::as_str).unwrap_or_default();
+
+                    scope.insert(i.to_string(), bound(param));
+                }
+
+                let (value, flavor) = match self.context.get(&name) {
+                    Some(variable) => (variable.value.clone(), variable.flavor),
+                    None => return String::new(),
+                };
+
+                self.context.push_scope(scope);
+                let result = match flavor {
+                    Flavor::Recursive => self.expand(&value),
+                    Flavor::Simple => value,
+                };
+                self.context.pop_scope();
+
+                result
+            }
+            "value" => {
+                let name = self.expand(arguments);
+
+                self.context
+                    .get(&name)
+                    .map(|variable| variable.value.clone())
+                    .unwrap_or_default()
+            }
+            _ => unreachable!(
+                "control_function called for non control function: {}",
+                function
+            ),
+        }
+    }
+
+    /**
+     * Calls the builtin `function` with already expanded `params`, as `$(call function,params...)` does, ie. params
+     * are neither split again at commas, nor expanded again (except by functions expanding their arguments only
+     * when needed, eg. `if` and `foreach`, same as make)
+     *
+     * @returns None if `function` is not a builtin function
+     */
+    fn call_builtin(&mut self, function: &str, params: &[String]) -> Option<String> {
+        let flavor = match function {
+            "if" | "or" | "and" | "intcmp" | "foreach" | "let" => Flavor::Recursive,
+            _ => Flavor::Simple,
+        };
+
+        /* Each param is given as a reference to a variable, with a name no makefile can define */
+        let names: Vec<String> = (1..=params.len()).map(|i| format!(" {}", i)).collect();
+        let scope = names
+            .iter()
+            .zip(params)
+            .map(|(name, param)| {
+                (
+                    name.clone(),
+                    Variable::new(param.clone(), flavor, Origin::Automatic),
+                )
+            })
+            .collect();
+        let arguments: Vec<String> = names.iter().map(|name| format!("$({})", name)).collect();
+
+        self.context.push_scope(scope);
+        let result = self.call(function, &arguments.join(","));
+        self.context.pop_scope();
+
+        result
+    }
+}
@@ -0,0 +1,73 @@
+use super::{split_arguments, Expander};
+use crate::ast::Severity;
+use std::io::ErrorKind;
+
+impl Expander<'_> {
+    /**
+     * `$(file >name,text)`, `$(file >>name,text)` and `$(file <name)`, against the context's filesystem
+     *
+     * @note Same as make, a missing filename or a failed write is an error, while reading a missing file is empty
+     *
+     * @ref: https://www.gnu.org/software/make/manual/html_node/File-Function.html
+     */
+    pub(super) fn file_function(&mut self, arguments: &str) -> String {
+        let args = split_arguments(arguments, 2);
+        let operation = self.expand(args[0]);
+        let operation = operation.trim();
+        let text = args.get(1).map(|text| self.expand(text));
+
+        let (operator, name) = if let Some(name) = operation.strip_prefix(">>") {
+            (">>", name.trim_start())
+        } else if let Some(name) = operation.strip_prefix('>') {
+            (">", name.trim_start())
+        } else if let Some(name) = operation.strip_prefix('<') {
+            ("<", name.trim_start())
+        } else {
+            return self.fail(format!("Invalid file operation: {}", operation));
+        };
+
+        if name.is_empty() {
+            return self.fail("file: missing filename".to_string());
+        }
+
+        let path = self.context.root_makefile_dir.join(name);
+
+        if operator == "<" {
+            if text.is_some() {
+                return self.fail("file: too many arguments".to_string());
+            }
+
+            return match self.context.fs.read_to_string(&path) {
+                Ok(mut contents) => {
+                    if contents.ends_with('\n') {
+                        contents.pop();
+                    }
+
+                    contents
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+                Err(e) => self.fail(format!("read: {}: {}", name, e)),
+            };
+        }
+
+        /* Without a text only the file is created (or truncated), otherwise a newline is added if text doesn't end with one */
+        let contents = match text {
+            Some(text) if !text.ends_with('\n') => text + "\n",
+            Some(text) => text,
+            None => String::new(),
+        };
+
+        if let Err(e) = self.context.fs.write(&path, &contents, operator == ">>") {
+            return self.fail(format!("open: {}: {}", name, e));
+        }
+
+        String::new()
+    }
+
+    /* Same as `$(error)`, aborts evaluation with `message` */
+    fn fail(&mut self, message: String) -> String {
+        self.context.diagnose(Severity::Error, message);
+
+        String::new()
+    }
+}
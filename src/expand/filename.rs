@@ -0,0 +1,89 @@
+use super::Expander;
+use crate::fs::{glob, normalize};
+use std::path::Path;
+
+/* Index of the '.' starting the suffix of `name`, if any, ie. a '.' in the last path component */
+fn suffix_start(name: &str) -> Option<usize> {
+    let dot = name.rfind('.')?;
+
+    match name.rfind('/') {
+        Some(slash) if slash > dot => None,
+        _ => Some(dot),
+    }
+}
+
+impl Expander<'_> {
+    /**
+     * File name functions, ie. `dir`, `notdir`, `suffix`, `basename`, `addsuffix`, `addprefix`,
+     * `join`, `wildcard`, `realpath` and `abspath`
+     *
+     * @note Relative paths are relative to the root makefile's directory
+     *
+     * @ref: https://www.gnu.org/software/make/manual/html_node/File-Name-Functions.html
+     */
+    pub(super) fn filename_function(&mut self, function: &str, arguments: &str) -> String {
+        let count = match function {
+            "addsuffix" | "addprefix" | "join" => 2,
+            _ => 1,
+        };
+
+        let args = match self.expand_arguments(function, arguments, count) {
+            Some(args) => args,
+            None => return String::new(),
+        };
+
+        let names = args[count - 1].split_whitespace();
+        let root_dir = self.context.root_makefile_dir.clone();
+
+        let result: Vec<String> = match function {
+            "dir" => names
+                .map(|name| match name.rfind('/') {
+                    Some(slash) => name[..=slash].to_string(),
+                    None => "./".to_string(),
+                })
+                .collect(),
+            "notdir" => names
+                .map(|name| name.rsplit('/').next().unwrap_or(name).to_string())
+                .collect(),
+            "suffix" => names
+                .filter_map(|name| suffix_start(name).map(|dot| name[dot..].to_string()))
+                .collect(),
+            "basename" => names
+                .map(|name| match suffix_start(name) {
+                    Some(dot) => name[..dot].to_string(),
+                    None => name.to_string(),
+                })
+                .collect(),
+            "addsuffix" => names.map(|name| name.to_string() + &args[0]).collect(),
+            "addprefix" => names.map(|name| args[0].clone() + name).collect(),
+            "join" => {
+                let first: Vec<&str> = args[0].split_whitespace().collect();
+                let second: Vec<&str> = args[1].split_whitespace().collect();
+
+                (0..first.len().max(second.len()))
+                    .map(|i| {
+                        first.get(i).copied().unwrap_or_default().to_string()
+                            + second.get(i).copied().unwrap_or_default()
+                    })
+                    .collect()
+            }
+            "wildcard" => names
+                .flat_map(|pattern| glob(self.context.fs.as_ref(), &root_dir, pattern))
+                .collect(),
+            "realpath" => names
+                .filter_map(|name| self.context.fs.canonicalize(&root_dir.join(name)).ok())
+                .map(|path| path.display().to_string())
+                .collect(),
+            "abspath" => names
+                .map(|name| normalize(&root_dir.join(Path::new(name))))
+                .map(|path| path.display().to_string())
+                .collect(),
+            _ => unreachable!(
+                "filename_function called for non file name function: {}",
+                function
+            ),
+        };
+
+        result.join(" ")
+    }
+}
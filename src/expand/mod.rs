@@ -0,0 +1,366 @@
+mod control;
+mod file;
+mod filename;
+mod text;
+
+use crate::ast::{Context, Flavor, Origin, Severity, Variable};
+use crate::pattern::patsubst;
+use std::collections::BTreeMap;
+
+/* Suffixes make knows by default, ie. its default `.SUFFIXES`, see `$*` in automatic_variables */
+const DEFAULT_SUFFIXES: &[&str] = &[
+    ".out", ".a", ".ln", ".o", ".c", ".cc", ".C", ".cpp", ".p", ".f", ".F", ".m", ".r", ".y", ".l",
+    ".ym", ".yl", ".s", ".S", ".mod", ".sym", ".def", ".h", ".info", ".dvi", ".tex", ".texinfo",
+    ".texi", ".txinfo", ".w", ".ch", ".web", ".sh", ".elc", ".el",
+];
+
+impl Context {
+    /**
+     * Expands all variable references ie. `$(VAR)`, `${VAR}`, `$V` and `$$` in `text`
+     *
+     * @note Undefined variables expand to an empty string, as in make
+     */
+    pub fn expand(&mut self, text: &str) -> String {
+        Expander {
+            context: self,
+            expanding: Vec::new(),
+        }
+        .expand(text)
+    }
+}
+
+/**
+ * Binds the automatic variables (`$@`, `$<`, `$^`, `$+`, `$?`, `$*`, `$|` along with their `D` and `F` forms)
+ * for the recipe of `target`
+ *
+ * @param normal, order_only Expanded prerequisites of target
+ * @param stem The part matched by `%` if target was matched by a pattern rule
+ * @note Timestamps for `$?` are read through `context.fs`, eg. to see files written by `$(file)`
+ */
+pub fn automatic_variables(
+    context: &Context,
+    target: &str,
+    normal: &[String],
+    order_only: &[String],
+    stem: Option<&str>,
+) -> BTreeMap<String, Variable> {
+    let mut unique: Vec<&str> = Vec::new();
+    for prerequisite in normal {
+        if !unique.contains(&prerequisite.as_str()) {
+            unique.push(prerequisite);
+        }
+    }
+
+    /* Without timestamps to compare, a missing target is considered older than everything */
+    let modified = |path: &str| {
+        context
+            .fs
+            .modified(&context.root_makefile_dir.join(path))
+            .ok()
+    };
+    let newer: Vec<&str> = match modified(target) {
+        Some(target_time) => unique
+            .iter()
+            .copied()
+            .filter(|p| modified(p).is_none_or(|time| time > target_time))
+            .collect(),
+        None => unique.clone(),
+    };
+
+    /* For explicit rules, `$*` is the target without its suffix, if it's a suffix make knows, else empty */
+    let stem = match stem {
+        Some(stem) => stem.to_string(),
+        None => DEFAULT_SUFFIXES
+            .iter()
+            .find_map(|suffix| target.strip_suffix(suffix))
+            .unwrap_or_default()
+            .to_string(),
+    };
+
+    let values = [
+        ("@", target.to_string()),
+        ("<", normal.first().cloned().unwrap_or_default()),
+        ("^", unique.join(" ")),
+        ("+", normal.join(" ")),
+        ("?", newer.join(" ")),
+        ("*", stem),
+        ("|", order_only.join(" ")),
+    ];
+
+    let mut scope = BTreeMap::new();
+
+    for (name, value) in values {
+        let dirs: Vec<&str> = value
+            .split_whitespace()
+            .map(|word| match word.rfind('/') {
+                Some(0) => "/",
+                Some(slash) => &word[..slash],
+                None => ".",
+            })
+            .collect();
+        let files: Vec<&str> = value
+            .split_whitespace()
+            .map(|word| word.rsplit('/').next().unwrap_or(word))
+            .collect();
+
+        for (name, value) in [
+            (format!("{}D", name), dirs.join(" ")),
+            (format!("{}F", name), files.join(" ")),
+            (name.to_string(), value),
+        ] {
+            scope.insert(
+                name,
+                Variable::new(value, Flavor::Simple, Origin::Automatic),
+            );
+        }
+    }
+
+    scope
+}
+
+/**
+ * Returns index of the `close` paren matching the `open` paren at start of `text`
+ *
+ * @note Only parens of the same kind are counted, same as make
+ */
+pub fn find_closing(text: &str, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+
+    for (i, c) in text.char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+
+    None
+}
+
+/**
+ * Splits arguments of a function call at commas outside of parens, into at most `count` arguments,
+ * the last one gets the rest of the text (including any commas), same as make
+ *
+ * eg. `a,$(subst x,y,z),b,c` with count 3 is split into ["a", "$(subst x,y,z)", "b,c"]
+ */
+pub fn split_arguments(arguments: &str, count: usize) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in arguments.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            ',' if depth == 0 && result.len() + 1 < count => {
+                result.push(&arguments[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    result.push(&arguments[start..]);
+    result
+}
+
+struct Expander<'a> {
+    context: &'a mut Context,
+    /* Names of recursively expanded variables currently being expanded, to catch self references */
+    expanding: Vec<String>,
+}
+
+impl Expander<'_> {
+    fn expand(&mut self, text: &str) -> String {
+        if self.context.aborted().is_some() {
+            /* Nothing is evaluated after an `$(error)` */
+            return String::new();
+        }
+
+        let mut result = String::new();
+        let mut rest = text;
+
+        while let Some(dollar) = rest.find('$') {
+            result += &rest[..dollar];
+            rest = &rest[dollar + 1..];
+
+            match rest.chars().next() {
+                None => { /* A lone '$' at end of text expands to nothing */ }
+                Some('$') => {
+                    result.push('$');
+                    rest = &rest[1..];
+                }
+                Some(open @ ('(' | '{')) => {
+                    let close = if open == '(' { ')' } else { '}' };
+
+                    match find_closing(rest, open, close) {
+                        Some(end) => {
+                            let reference = &rest[1..end];
+                            rest = &rest[end + 1..];
+
+                            result += &self.reference(reference);
+                        }
+                        None => {
+                            println!("❗ Unterminated variable reference: ${}", rest);
+                            rest = "";
+                        }
+                    }
+                }
+                Some(c) => {
+                    /* Single character variable, eg. `$@` or `$X` */
+                    result += &self.lookup(&c.to_string());
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+
+        result + rest
+    }
+
+    /* Expands contents of a `$(...)` or `${...}` */
+    fn reference(&mut self, reference: &str) -> String {
+        if let Some((function, arguments)) = reference.split_once([' ', '\t']) {
+            if let Some(result) = self.call(function, arguments.trim_start()) {
+                return result;
+            }
+        }
+
+        /* Name may itself contain references, eg. `$($(ARCH)_CFLAGS)` */
+        let reference = self.expand(reference);
+
+        /* Substitution reference, ie. `$(SRCS:.c=.o)` or `$(SRCS:%.c=build/%.o)` */
+        if let Some((name, substitution)) = reference.split_once(':') {
+            if let Some((from, to)) = substitution.split_once('=') {
+                let value = self.lookup(name);
+
+                return if from.contains('%') {
+                    patsubst(from, to, &value)
+                } else {
+                    /* `$(SRCS:.c=.o)` is same as `$(SRCS:%.c=%.o)` */
+                    patsubst(&format!("%{}", from), &format!("%{}", to), &value)
+                };
+            }
+        }
+
+        self.lookup(&reference)
+    }
+
+    /**
+     * Calls the make function `function`, such as `$(origin CC)`
+     *
+     * @returns None if `function` is not a known function, ie. it is a variable reference
+     */
+    fn call(&mut self, function: &str, arguments: &str) -> Option<String> {
+        let result = match function {
+            "origin" => {
+                let name = self.expand(arguments);
+
+                match self.context.get(&name) {
+                    Some(variable) => variable.origin.as_str(),
+                    None => "undefined",
+                }
+                .to_string()
+            }
+            "flavor" => {
+                let name = self.expand(arguments);
+
+                match self.context.get(&name) {
+                    Some(variable) => variable.flavor.as_str(),
+                    None => "undefined",
+                }
+                .to_string()
+            }
+            "eval" => {
+                /* Parsed as makefile syntax by whoever is reading the Stream, once the current line is handled */
+                let text = self.expand(arguments);
+                self.context.eval(text);
+
+                String::new()
+            }
+            "error" | "warning" | "info" => {
+                let message = self.expand(arguments);
+                let severity = match function {
+                    "error" => Severity::Error,
+                    "warning" => Severity::Warning,
+                    _ => Severity::Info,
+                };
+
+                self.context.diagnose(severity, message);
+
+                String::new()
+            }
+            "subst" | "patsubst" | "strip" | "findstring" | "filter" | "filter-out" | "sort"
+            | "word" | "words" | "wordlist" | "firstword" | "lastword" => {
+                self.text_function(function, arguments)
+            }
+            "dir" | "notdir" | "suffix" | "basename" | "addsuffix" | "addprefix" | "join"
+            | "wildcard" | "realpath" | "abspath" => self.filename_function(function, arguments),
+            "file" => self.file_function(arguments),
+            "if" | "or" | "and" | "intcmp" | "foreach" | "let" | "call" | "value" => {
+                self.control_function(function, arguments)
+            }
+            _ => return None,
+        };
+
+        Some(result)
+    }
+
+    fn insufficient_arguments(&self, function: &str, count: usize) -> String {
+        println!(
+            "❗ Insufficient number of arguments ({}) to function '{}'",
+            count, function
+        );
+
+        String::new()
+    }
+
+    /**
+     * Splits and expands arguments of `function`, which takes `count` arguments
+     *
+     * @returns None if there are less than `count` arguments
+     */
+    fn expand_arguments(
+        &mut self,
+        function: &str,
+        arguments: &str,
+        count: usize,
+    ) -> Option<Vec<String>> {
+        let arguments = split_arguments(arguments, count);
+
+        if arguments.len() < count {
+            self.insufficient_arguments(function, arguments.len());
+            return None;
+        }
+
+        Some(arguments.iter().map(|a| self.expand(a)).collect())
+    }
+
+    fn lookup(&mut self, name: &str) -> String {
+        let (value, flavor) = match self.context.get(name) {
+            Some(variable) => (variable.value.clone(), variable.flavor),
+            None => return String::new(),
+        };
+
+        if flavor == Flavor::Simple {
+            return value;
+        }
+
+        if self.expanding.iter().any(|n| n == name) {
+            println!(
+                "❗ Recursive variable '{}' references itself (eventually)",
+                name
+            );
+            return String::new();
+        }
+
+        self.expanding.push(name.to_string());
+        let expanded = self.expand(&value);
+        self.expanding.pop();
+
+        expanded
+    }
+}
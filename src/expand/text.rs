@@ -0,0 +1,196 @@
+use super::Expander;
+use crate::pattern::{match_pattern, patsubst};
+
+/* Parses a word index as used by `word` and `wordlist`, ie. a number starting from 1 */
+fn parse_index(function: &str, index: &str) -> Option<usize> {
+    match index.trim().parse::<usize>() {
+        Ok(index) if index > 0 => Some(index),
+        _ => {
+            println!(
+                "❗ Non-numeric or zero index '{}' given to function '{}'",
+                index.trim(),
+                function
+            );
+            None
+        }
+    }
+}
+
+impl Expander<'_> {
+    /**
+     * Text functions, ie. `subst`, `patsubst`, `strip`, `findstring`, `filter`, `filter-out`, `sort`,
+     * `word`, `words`, `wordlist`, `firstword` and `lastword`
+     *
+     * @ref: https://www.gnu.org/software/make/manual/html_node/Text-Functions.html
+     */
+    pub(super) fn text_function(&mut self, function: &str, arguments: &str) -> String {
+        let count = match function {
+            "subst" | "patsubst" | "wordlist" => 3,
+            "findstring" | "filter" | "filter-out" | "word" => 2,
+            _ => 1,
+        };
+
+        let args = match self.expand_arguments(function, arguments, count) {
+            Some(args) => args,
+            None => return String::new(),
+        };
+
+        match function {
+            "subst" => {
+                if args[0].is_empty() {
+                    /* Empty string is said to be found at the end of text */
+                    args[2].clone() + &args[1]
+                } else {
+                    args[2].replace(&args[0], &args[1])
+                }
+            }
+            "patsubst" => patsubst(args[0].trim(), args[1].trim(), &args[2]),
+            "strip" => args[0].split_whitespace().collect::<Vec<_>>().join(" "),
+            "findstring" => {
+                if args[1].contains(&args[0]) {
+                    args[0].clone()
+                } else {
+                    String::new()
+                }
+            }
+            "filter" | "filter-out" => {
+                let keep = function == "filter";
+                let patterns: Vec<&str> = args[0].split_whitespace().collect();
+
+                args[1]
+                    .split_whitespace()
+                    .filter(|word| {
+                        patterns
+                            .iter()
+                            .any(|pattern| match_pattern(pattern, word).is_some())
+                            == keep
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            }
+            "sort" => {
+                let mut words: Vec<&str> = args[0].split_whitespace().collect();
+                words.sort_unstable();
+                words.dedup();
+
+                words.join(" ")
+            }
+            "word" => match parse_index(function, &args[0]) {
+                Some(n) => args[1]
+                    .split_whitespace()
+                    .nth(n - 1)
+                    .unwrap_or_default()
+                    .to_string(),
+                None => String::new(),
+            },
+            "words" => args[0].split_whitespace().count().to_string(),
+            "wordlist" => {
+                let start = parse_index(function, &args[0]);
+                let end = args[1].trim().parse::<usize>().ok();
+
+                match (start, end) {
+                    (Some(start), Some(end)) if end >= start => args[2]
+                        .split_whitespace()
+                        .skip(start - 1)
+                        .take(end - start + 1)
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    _ => String::new(),
+                }
+            }
+            "firstword" => args[0]
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            "lastword" => args[0]
+                .split_whitespace()
+                .last()
+                .unwrap_or_default()
+                .to_string(),
+            _ => unreachable!("text_function called for non text function: {}", function),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{Context, Origin};
+    use crate::expand::{find_closing, split_arguments};
+    use std::path::PathBuf;
+
+    fn expand(text: &str) -> String {
+        Context::new(PathBuf::from(".")).expand(text)
+    }
+
+    /* Examples from https://www.gnu.org/software/make/manual/html_node/Text-Functions.html */
+    #[test]
+    fn manual_examples() {
+        assert_eq!(
+            expand("$(subst ee,EE,feet on the street)"),
+            "fEEt on the strEEt"
+        );
+        assert_eq!(expand("$(patsubst %.c,%.o,x.c.c bar.c)"), "x.c.o bar.o");
+        assert_eq!(expand("$(strip  a b  c )"), "a b c");
+        assert_eq!(expand("$(findstring a,a b c)"), "a");
+        assert_eq!(expand("$(findstring a,b c)"), "");
+        assert_eq!(expand("$(sort foo bar lose)"), "bar foo lose");
+        assert_eq!(expand("$(word 2, foo bar baz)"), "bar");
+        assert_eq!(expand("$(wordlist 2, 3, foo bar baz)"), "bar baz");
+        assert_eq!(expand("$(words foo bar baz)"), "3");
+        assert_eq!(expand("$(firstword foo bar)"), "foo");
+        assert_eq!(expand("$(lastword foo bar)"), "bar");
+    }
+
+    #[test]
+    fn filter_examples() {
+        let mut context = Context::new(PathBuf::from("."));
+        context.set(
+            "sources",
+            ":=",
+            "foo.c bar.c baz.s ugh.h".to_string(),
+            Origin::File,
+        );
+        context.set(
+            "objects",
+            "=",
+            "main1.o foo.o main2.o bar.o".to_string(),
+            Origin::File,
+        );
+        context.set("mains", "=", "main1.o main2.o".to_string(), Origin::File);
+
+        assert_eq!(
+            context.expand("$(filter %.c %.s,$(sources))"),
+            "foo.c bar.c baz.s"
+        );
+        assert_eq!(
+            context.expand("$(filter-out $(mains),$(objects))"),
+            "foo.o bar.o"
+        );
+    }
+
+    #[test]
+    fn nested_calls() {
+        assert_eq!(expand("$(subst a,b,$(subst x,a,xyz))"), "byz");
+        assert_eq!(expand("$(words $(wordlist 1,2,$(sort c b a)))"), "2");
+    }
+
+    #[test]
+    fn arguments_split_outside_parens() {
+        assert_eq!(
+            split_arguments("a,$(subst x,y,z),b,c", 3),
+            ["a", "$(subst x,y,z)", "b,c"]
+        );
+        assert_eq!(split_arguments("${x,y},z", 2), ["${x,y}", "z"]);
+        assert_eq!(split_arguments("a,b", 1), ["a,b"]);
+        assert_eq!(split_arguments("", 3), [""]);
+    }
+
+    #[test]
+    fn closing_paren_of_same_kind() {
+        assert_eq!(find_closing("(a (b) c) d", '(', ')'), Some(8));
+        /* Braces don't count while looking for a ')' */
+        assert_eq!(find_closing("(a { b) }", '(', ')'), Some(6));
+        assert_eq!(find_closing("(a (b)", '(', ')'), None);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::pattern::glob_match;
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+/**
+ * Filesystem as seen by the makefile functions (eg. `$(wildcard)`), so the analysis can be done
+ * against something other than the real filesystem
+ */
+pub trait FileSystem {
+    fn exists(&self, path: &Path) -> bool;
+
+    fn is_dir(&self, path: &Path) -> bool;
+
+    /* Names of entries in the directory at `path` */
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<String>>;
+
+    /* Absolute path with all symlinks, `.` and `..` resolved, fails if path doesn't exist */
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+
+    /* Last modification time of the file at `path` */
+    fn modified(&self, path: &Path) -> io::Result<SystemTime>;
+
+    /* Creates or truncates the file at `path` to `contents`, or appends `contents` if `append` */
+    fn write(&mut self, path: &Path, contents: &str, append: bool) -> io::Result<()>;
+}
+
+pub struct RealFileSystem {}
+
+impl FileSystem for RealFileSystem {
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+
+        for entry in std::fs::read_dir(path)? {
+            names.push(entry?.file_name().to_string_lossy().to_string());
+        }
+
+        Ok(names)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        path.canonicalize()
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        std::fs::metadata(path)?.modified()
+    }
+
+    fn write(&mut self, path: &Path, contents: &str, append: bool) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+
+        file.write_all(contents.as_bytes())
+    }
+}
+
+/**
+ * Reads from `base`, but keeps written files in memory, so analysing a makefile (eg. one doing `$(file >...)`)
+ * never modifies the tree
+ *
+ * @note Written files are visible to later reads, and to functions like `$(wildcard)`
+ */
+pub struct OverlayFileSystem {
+    base: Box<dyn FileSystem>,
+    /* Contents of written files, keyed by their normalized path */
+    files: BTreeMap<PathBuf, String>,
+    /* When each of `files` was last written */
+    written_at: BTreeMap<PathBuf, SystemTime>,
+}
+
+impl OverlayFileSystem {
+    pub fn new(base: Box<dyn FileSystem>) -> Self {
+        OverlayFileSystem {
+            base,
+            files: BTreeMap::new(),
+            written_at: BTreeMap::new(),
+        }
+    }
+}
+
+impl FileSystem for OverlayFileSystem {
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(&normalize(path)) || self.base.exists(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.base.is_dir(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        let path = normalize(path);
+        let written = self
+            .files
+            .keys()
+            .filter(|file| file.parent() == Some(path.as_path()))
+            .filter_map(|file| file.file_name())
+            .map(|name| name.to_string_lossy().to_string());
+
+        let mut names = match self.base.read_dir(&path) {
+            Ok(names) => names,
+            Err(_) if self.files.keys().any(|file| file.parent() == Some(&path)) => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        for name in written {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        Ok(names)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let normalized = normalize(path);
+
+        match (normalized.parent(), normalized.file_name()) {
+            (Some(parent), Some(name)) if self.files.contains_key(&normalized) => {
+                Ok(self.base.canonicalize(parent)?.join(name))
+            }
+            _ => self.base.canonicalize(path),
+        }
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        match self.files.get(&normalize(path)) {
+            Some(contents) => Ok(contents.clone()),
+            None => self.base.read_to_string(path),
+        }
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        match self.written_at.get(&normalize(path)) {
+            Some(time) => Ok(*time),
+            None => self.base.modified(path),
+        }
+    }
+
+    fn write(&mut self, path: &Path, contents: &str, append: bool) -> io::Result<()> {
+        let path = normalize(path);
+
+        /* Same as a real write, the directory must exist */
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() && !self.is_dir(parent) {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "No such file or directory",
+                ));
+            }
+        }
+
+        let mut new_contents = match append {
+            true => self.read_to_string(&path).unwrap_or_default(),
+            false => String::new(),
+        };
+        new_contents += contents;
+
+        self.written_at.insert(path.clone(), SystemTime::now());
+        self.files.insert(path, new_contents);
+        Ok(())
+    }
+}
+
+/**
+ * Resolves `.` and `..` in `path` without looking at the filesystem, ie. symlinks are not followed
+ */
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+/**
+ * Files matching the wildcard `pattern` (eg. `*.c`), relative paths are looked up in `base_dir`
+ *
+ * @returns Matching paths as written in pattern (ie. relative paths stay relative), sorted
+ */
+pub fn glob(fs: &dyn FileSystem, base_dir: &Path, pattern: &str) -> Vec<String> {
+    /* Pairs of path to be given to `fs`, and path as it will be returned */
+    let mut matches = vec![if pattern.starts_with('/') {
+        (PathBuf::from("/"), String::from("/"))
+    } else {
+        (base_dir.to_path_buf(), String::new())
+    }];
+
+    let components: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+
+    for (i, component) in components.iter().enumerate() {
+        let is_last = i + 1 == components.len();
+        let mut next = Vec::new();
+
+        for (path, shown) in matches {
+            let join = |name: &str| {
+                let shown = if shown.is_empty() || shown.ends_with('/') {
+                    shown.clone() + name
+                } else {
+                    shown.clone() + "/" + name
+                };
+
+                (path.join(name), shown)
+            };
+
+            if component.contains(['*', '?', '[']) {
+                let mut names = fs.read_dir(&path).unwrap_or_default();
+                names.sort();
+
+                for name in names {
+                    if glob_match(component, &name) {
+                        next.push(join(&name));
+                    }
+                }
+            } else {
+                next.push(join(component));
+            }
+        }
+
+        /* Only directories can have more components after them */
+        matches = next
+            .into_iter()
+            .filter(|(path, _)| fs.exists(path) && (is_last || fs.is_dir(path)))
+            .collect();
+    }
+
+    if pattern.ends_with('/') {
+        for (_, shown) in &mut matches {
+            shown.push('/');
+        }
+    }
+
+    matches.into_iter().map(|(_, shown)| shown).collect()
+}
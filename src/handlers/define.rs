@@ -0,0 +1,112 @@
+use super::split_assignment;
+use crate::ast::{Context, Origin, Severity};
+use crate::nodes::{ASTNode, DefineASTNode};
+use crate::stream::Stream;
+
+/* handle multi-line variables, ie. `define NAME [=|:=|+=]` ... `endef` */
+pub struct DefineHandler {}
+
+impl DefineHandler {
+    pub fn handle(line: &str, stream: &mut Stream, context: &mut Context) -> Box<dyn ASTNode> {
+        let mut define = DefineHandler::read(line, stream, context);
+
+        let origin = if define.is_override {
+            Origin::Override
+        } else {
+            Origin::File
+        };
+        define.name = context.expand(&define.name);
+        context.set(&define.name, &define.operator, define.value.clone(), origin);
+
+        Box::new(define)
+    }
+
+    /**
+     * Reads the `define` starting at `line` along with its body, without defining the variable, eg. for a `define`
+     * in a branch not taken
+     *
+     * @note Name of the variable is as written, ie. unexpanded
+     */
+    pub fn read(line: &str, stream: &mut Stream, context: &mut Context) -> DefineASTNode {
+        let opening = stream.location();
+        let line = line.trim();
+
+        let (is_override, line) = match line.strip_prefix("override") {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, line),
+        };
+
+        let header = line
+            .strip_prefix("define")
+            .expect("DefineHandler: Expected line of form \"[override] define NAME [=]\"")
+            .trim();
+
+        /* Operator is optional, a bare `define NAME` is the same as `define NAME =` */
+        let (name, operator) = match split_assignment(header) {
+            Some((name, operator, _)) => (name, operator),
+            None => (header, "="),
+        };
+
+        let operator = if [":::=", "::=", ":=", "+=", "?=", "="].contains(&operator) {
+            operator
+        } else {
+            println!(
+                "❗ Unsupported operator in define, treating as '=': {}",
+                operator
+            );
+            "="
+        };
+
+        let (body, closed) = DefineHandler::read_body(stream);
+        if !closed {
+            /* Same as make, it's fatal, and reported at the `define` */
+            context.location = opening;
+            context.diagnose(
+                Severity::Error,
+                "missing 'endef', unterminated 'define'".to_string(),
+            );
+        }
+
+        DefineASTNode {
+            name: name.to_string(),
+            operator: operator.to_string(),
+            value: body.join("\n"),
+            is_override,
+        }
+    }
+
+    /* Whether `line` starts a `define`, ie. `define NAME` or `override define NAME` */
+    pub fn is_define(line: &str) -> bool {
+        let line = line.trim();
+
+        line.starts_with("define ") || line.starts_with("override define ")
+    }
+
+    /**
+     * Reads lines of a `define` body verbatim, till the `endef` matching the `define` just read (which is also read)
+     *
+     * @returns Lines of the body, and whether the `endef` was found before end of the makefile
+     */
+    fn read_body(stream: &mut Stream) -> (Vec<String>, bool) {
+        let mut body = Vec::new();
+        let mut depth = 0;
+
+        while !stream.eof {
+            let next_line = stream.read_verbatim_line();
+            let directive = next_line.trim();
+
+            if directive == "endef" || directive.starts_with("endef ") {
+                if depth == 0 {
+                    return (body, true);
+                }
+                depth -= 1;
+            } else if DefineHandler::is_define(directive) {
+                depth += 1;
+            }
+
+            body.push(next_line);
+        }
+
+        (body, false)
+    }
+}
@@ -1,2 +1,3 @@
 /* @Dropped May resume in future though, handle where ./... some executable started */
+#[allow(dead_code)]
 pub struct ExecutableHandler {}
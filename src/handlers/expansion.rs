@@ -0,0 +1,43 @@
+use super::Handler;
+use crate::ast::Context;
+use crate::expand::find_closing;
+use crate::nodes::{ASTNode, ExpansionASTNode};
+
+/* Whether `line` is a single `$(...)` or `${...}`, and nothing else (eg. not `$(OBJS): foo.h`) */
+pub fn is_expansion(line: &str) -> bool {
+    let (open, close) = match line.get(..2) {
+        Some("$(") => ('(', ')'),
+        Some("${") => ('{', '}'),
+        _ => return false,
+    };
+
+    find_closing(&line[1..], open, close) == Some(line.len() - 2)
+}
+
+/* handle lines which are only a function call or variable reference, eg. `$(eval $(call TEMPLATE,foo))` */
+pub struct ExpansionHandler {}
+
+impl Handler for ExpansionHandler {
+    fn handle(line: &str, context: Option<&mut Context>) -> Box<dyn ASTNode> {
+        let context =
+            context.expect("ExpansionHandler requires the context to expand the expression");
+
+        let line = line.trim();
+
+        if !is_expansion(line) {
+            panic!(
+                "ExpansionHandler: Can only handle lines which are a single '$(...)' or '${{...}}'"
+            );
+        }
+
+        /* Same as make, expanding to anything other than whitespace is a syntax error */
+        let result = context.expand(line);
+        if !result.trim().is_empty() {
+            println!("❗ Missing separator, line expanded to: {}", result.trim());
+        }
+
+        Box::new(ExpansionASTNode {
+            expression: line.to_string(),
+        })
+    }
+}
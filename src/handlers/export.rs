@@ -1,5 +1,5 @@
-use super::Handler;
-use crate::ast::Context;
+use super::{split_assignment, Handler};
+use crate::ast::{Context, Origin};
 use crate::nodes::{ASTNode, ExportASTNode, UnExportASTNode};
 
 pub struct ExportHandler {}
@@ -18,20 +18,30 @@ impl Handler for ExportHandler {
 
         if token == "export" {
             // BUG: Makefile Line 90 && will get ignored
-            let (var_name, var_value) = var_expr
-                .split_once('=')
-                .expect("Expected var=value expression after \"export\" token");
-
-            context.set(var_name.to_string(), var_value.to_string());
-
-            Box::new(ExportASTNode::new(
-                var_name.to_string(),
-                var_value.to_string(),
-            ))
+            match split_assignment(var_expr) {
+                Some((var_name, operator, var_value)) => {
+                    let var_name = context.expand(var_name);
+
+                    context.set(&var_name, operator, var_value.to_string(), Origin::File);
+                    context.export(&var_name);
+
+                    Box::new(ExportASTNode::new(var_name, var_value.to_string()))
+                }
+                None => {
+                    /* `export VAR1 VAR2`, only marks already defined variables as exported */
+                    for var_name in context.expand(var_expr).split_whitespace() {
+                        context.export(var_name);
+                    }
+
+                    Box::new(ExportASTNode::new(var_expr.to_string(), String::new()))
+                }
+            }
         } else if token == "unexport" {
             let var_name = var_expr;
 
-            context.unset(var_name);
+            for var_name in context.expand(var_name).split_whitespace() {
+                context.unexport(var_name);
+            }
             Box::new(UnExportASTNode::new(var_name.to_string()))
         } else {
             panic!(
@@ -1,9 +1,13 @@
+use std::path::PathBuf;
 use time::Instant;
 
-use crate::ast::Context;
-use crate::handlers::GenericStepHandler;
-use crate::nodes::{ElseASTNode, IfASTNode};
-use crate::stream::Stream;
+use super::target::TargetStepHandler;
+use crate::ast::{Context, Severity};
+use crate::handlers::{DefineHandler, GenericStepHandler};
+use crate::nodes::{
+    ASTNode, Condition, ElseASTNode, IfASTNode, TargetGenericStep, UnhandledASTNode,
+};
+use crate::stream::{Location, Stream};
 
 // https://users.rust-lang.org/t/show-value-only-in-debug-mode/43686/2
 macro_rules! debugln {
@@ -21,92 +25,333 @@ macro_rules! debugln {
     };
 }
 
+/* Splits a conditional directive line into the directive and its condition, eg. `ifdef DEBUG` into ("ifdef", "DEBUG") */
+fn split_conditional(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+
+    ["ifeq", "ifneq", "ifdef", "ifndef"]
+        .into_iter()
+        .find_map(|directive| {
+            let condition = line.strip_prefix(directive)?;
+
+            /* `ifeq(a,b)` is valid too, but `ifdefined` is not a directive */
+            match condition.chars().next() {
+                Some(' ' | '\t' | '(') => Some((directive, condition.trim())),
+                _ => None,
+            }
+        })
+}
+
+/**
+ * Parses the condition of an `ifeq`/`ifneq`, ie. `(a,b)`, `"a" "b"`, `'a' 'b'` or a mix of quotes like `"a" 'b'`
+ *
+ * @note Same as make, in the parenthesised form whitespace before the comma and after it is stripped, and
+ * commas inside nested parens (eg. `$(subst a,b,$(X))`) don't separate the arguments
+ *
+ * @returns None if the syntax is invalid
+ */
+fn parse_comparison(condition: &str) -> Option<(String, String)> {
+    let (lhs, rest) = match condition.chars().next()? {
+        '(' => {
+            let mut depth = 0;
+            let comma = condition[1..].char_indices().find_map(|(i, c)| {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    ',' if depth <= 0 => return Some(i + 1),
+                    _ => {}
+                }
+                None
+            })?;
+
+            let rest = condition[comma + 1..].trim_start();
+
+            /* Closing paren matching the opening one, ie. one not closing a paren opened in rhs */
+            let mut depth = 0;
+            let close = rest.char_indices().find_map(|(i, c)| {
+                match c {
+                    '(' => depth += 1,
+                    ')' if depth <= 0 => return Some(i),
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                None
+            })?;
+
+            warn_extraneous(&rest[close + 1..]);
+            return Some((
+                condition[1..comma].trim_end().to_string(),
+                rest[..close].to_string(),
+            ));
+        }
+        quote @ ('"' | '\'') => {
+            let end = condition[1..].find(quote)? + 1;
+
+            (&condition[1..end], condition[end + 1..].trim_start())
+        }
+        _ => return None,
+    };
+
+    let quote = rest.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+    let end = rest[1..].find(quote)? + 1;
+
+    warn_extraneous(&rest[end + 1..]);
+    Some((lhs.to_string(), rest[1..end].to_string()))
+}
+
+/* Same as make, text after the condition is ignored */
+fn warn_extraneous(text: &str) {
+    if !text.trim().is_empty() {
+        println!(
+            "❗ Ignoring extraneous text after condition: {}",
+            text.trim()
+        );
+    }
+}
+
+/**
+ * Parses the condition of a conditional directive, eg. `ifneq ($(ARCH),x86_64)` or `ifdef DEBUG`
+ *
+ * @returns None if the line isn't a conditional directive, or the condition's syntax is invalid
+ */
+fn parse_condition(line: &str) -> Option<Condition> {
+    let (directive, condition) = split_conditional(line)?;
+
+    match directive {
+        "ifeq" => parse_comparison(condition).map(|(lhs, rhs)| Condition::Eq(lhs, rhs)),
+        "ifneq" => parse_comparison(condition).map(|(lhs, rhs)| Condition::Ne(lhs, rhs)),
+        /* Name may be a reference too, eg. `ifdef $(ARCH)_CFLAGS` */
+        _ if condition.is_empty() => None,
+        "ifdef" => Some(Condition::Defined(condition.to_string())),
+        _ => Some(Condition::NotDefined(condition.to_string())),
+    }
+}
+
+/* Whether `line` starts a conditional, ie. is an `ifeq`, `ifneq`, `ifdef` or `ifndef` directive */
+pub fn is_conditional(line: &str) -> bool {
+    split_conditional(line).is_some()
+}
+
+/* Splits an `else` or `endif` directive line into the directive and the text after it, eg. `else ifdef X` into ("else", "ifdef X") */
+pub fn split_branch_directive(line: &str) -> Option<(&'static str, &str)> {
+    let line = line.trim();
+
+    ["else", "endif"].into_iter().find_map(|directive| {
+        let rest = line.strip_prefix(directive)?;
+
+        match rest.chars().next() {
+            None | Some(' ' | '\t' | '#') => Some((directive, rest.trim())),
+            _ => None,
+        }
+    })
+}
+
+/**
+ * Checks the next line of `stream` can still be a part of the conditional opened at `opening`, in the file `source`
+ *
+ * @param `directive` `else`/`endif` directive on the next line, if any
+ * @returns false after reporting an error, if the file ended without an `endif`, or the `else`/`endif` is from
+ * another file (eg. an included one)
+ */
+fn check_balance(
+    stream: &Stream,
+    context: &mut Context,
+    source: usize,
+    opening: &Location,
+    directive: Option<(&str, &str)>,
+) -> bool {
+    if stream.eof || !stream.is_reading(source) {
+        /* Last line read is the last line of the file */
+        context.location = stream.location();
+        context.diagnose(
+            Severity::Error,
+            format!("missing 'endif', for conditional at {}", opening),
+        );
+
+        return false;
+    }
+
+    match directive {
+        Some((directive, _)) if stream.peek_source() != source => {
+            context.location = stream.peek_location();
+            context.diagnose(
+                Severity::Error,
+                format!(
+                    "extraneous '{}', conditional at {} must be closed in its own file",
+                    directive, opening
+                ),
+            );
+
+            false
+        }
+        _ => true,
+    }
+}
+
+/**
+ * Whether `condition` holds, with the variables in `context`
+ *
+ * @note Same as make, `ifdef` only checks whether the variable has a non-empty value, without expanding it
+ */
+fn evaluate(condition: &Condition, context: &mut Context) -> bool {
+    match condition {
+        Condition::Eq(lhs, rhs) => context.expand(lhs) == context.expand(rhs),
+        Condition::Ne(lhs, rhs) => context.expand(lhs) != context.expand(rhs),
+        Condition::Defined(name) | Condition::NotDefined(name) => {
+            let name = context.expand(name);
+            let defined = context
+                .get(name.trim())
+                .is_some_and(|variable| !variable.value.is_empty());
+
+            defined == matches!(condition, Condition::Defined(_))
+        }
+    }
+}
+
 /* handle if else conditions */
 pub struct IfHandler {}
 
 impl IfHandler {
     pub fn handle(line: &str, stream: &mut Stream, context: &mut Context) -> Box<IfASTNode> {
+        IfHandler::handle_conditional(line, stream, context, true, None)
+    }
+
+    /**
+     * Handles a conditional in a rule's recipe, lines starting with '\t' in it are recipe steps
+     *
+     * @param `current_dir` see TargetStepHandler
+     */
+    pub fn handle_recipe(
+        line: &str,
+        stream: &mut Stream,
+        context: &mut Context,
+        current_dir: &mut PathBuf,
+    ) -> Box<IfASTNode> {
+        IfHandler::handle_conditional(line, stream, context, true, Some(current_dir))
+    }
+
+    /**
+     * Handles the conditional starting at `line` till its `endif`, including any `else` branches
+     *
+     * @param `can_take` false if a branch can't be taken regardless of the conditions, ie. an enclosing conditional
+     * (or previous branch in the else chain) is not taken, then no condition is evaluated
+     * @param `recipe_dir` Some if the conditional is in a rule's recipe, see IfHandler::handle_recipe
+     */
+    fn handle_conditional(
+        line: &str,
+        stream: &mut Stream,
+        context: &mut Context,
+        can_take: bool,
+        mut recipe_dir: Option<&mut PathBuf>,
+    ) -> Box<IfASTNode> {
         let line = line.trim();
+        /* Same as make, in a recipe, lines starting with '\t' are recipe lines even if they look like directives */
+        let in_recipe = recipe_dir.is_some();
 
-        let condition = line
-            .split_once(' ')
-            .expect("Expected space between \"ifeq\" and a condition")
-            .1
-            .to_string();
+        let (directive, text) = split_conditional(line).unwrap_or_else(|| {
+            panic!(
+                "IfHandler: Expected one of ifeq, ifneq, ifdef or ifndef followed by a condition, found: {}",
+                line
+            )
+        });
+
+        let condition = match parse_condition(line) {
+            Some(condition) => condition,
+            None => {
+                /* Same as make, this aborts evaluation, so the condition is only kept for the AST */
+                context.diagnose(
+                    Severity::Error,
+                    format!("invalid syntax in conditional: {} {}", directive, text),
+                );
+
+                Condition::Eq(text.to_string(), String::new())
+            }
+        };
+
+        let active = can_take && evaluate(&condition, context);
 
         let mut if_node = IfASTNode {
             condition,
+            active,
             elseif_: None,
             else_: None,
             steps: Vec::new(),
         };
 
-        /* Current `next_line` will be storing the line just next to passed `line` which is something like 'ifeq ...', so we are done with passed `line` (condition known) */
-        let mut next_line = stream.peek_next_line().trim_start().to_string();
+        /* Assignments in the body are recorded as being inside this conditional */
+        context.conditionals.push(line.to_string());
+
+        /* Same as make, a conditional must end in the file it was opened in */
+        let opening = stream.location();
+        let source = stream.source();
 
         let start = Instant::now();
         let mut line_count = 0;
-        loop {
-            debugln!("Line: {}", &next_line);
-            if next_line.starts_with("endif") {
-                /* endif encountered, current line is `endif`, so read in next line (ie. our work done) and exit */
-                stream.read_line();
+        while context.aborted().is_none() {
+            /* Current `next_line` will be storing the line just next to passed `line` which is something like 'ifeq ...', so we are done with passed `line` (condition known) */
+            let next_line = stream.peek_next_line().trim_start().to_string();
+
+            let is_recipe_line = in_recipe && stream.peek_next_line().starts_with('\t');
+            let directive = split_branch_directive(&next_line).filter(|_| !is_recipe_line);
+
+            if !check_balance(stream, context, source, &opening, directive) {
                 break;
             }
 
-            if next_line.starts_with("else") {
-                let mut line = String::new();
-
-                /* loop to join all but first word/token in `next_line` */
-                for token in next_line.split_whitespace().skip(1) {
-                    line += token;
-                    line += " ";
+            debugln!("Line: {}", &next_line);
+            match directive {
+                Some(("endif", _)) => {
+                    /* endif encountered, current line is `endif`, so read in next line (ie. our work done) and exit */
+                    stream.read_line();
+                    break;
                 }
+                Some((_, line)) => {
+                    let line = line.to_string();
+
+                    /* Read in next line before recursing */
+                    stream.read_line();
+
+                    let if_line = context.conditionals.pop().unwrap_or_default();
+                    context.conditionals.push(format!("else of {}", if_line));
 
-                /* Read in next line before recursing */
-                stream.read_line();
-
-                if line.starts_with("ifeq") {
-                    /* else-ifeq block (with 'else' token removed)*/
-                    if_node.elseif_ = Some(IfHandler::handle(&line, stream, context));
-                } else {
-                    /* Simple else block - Just read in the lines in else blocks */
-
-                    let mut else_ = ElseASTNode { steps: Vec::new() };
-                    loop {
-                        let next_line = stream.peek_next_line().trim_start().to_string();
-
-                        if next_line.starts_with("endif") {
-                            /* endif encountered, if condition ends */
-                            break;
-                        }
-
-                        #[cfg(debug_assertions)]
-                        {
-                            line_count += 1;
-                        }
-                        else_
-                            .steps
-                            .push(GenericStepHandler::handle(&next_line, stream, context));
-                        stream.read_line();
+                    if is_conditional(&line) {
+                        /* else-ifeq (or else-ifdef etc.) block (with 'else' token removed)*/
+                        if_node.elseif_ = Some(IfHandler::handle_conditional(
+                            &line,
+                            stream,
+                            context,
+                            can_take && !active,
+                            recipe_dir,
+                        ));
+                    } else {
+                        /* Simple else block - Just read in the lines in else blocks */
+                        if_node.else_ = Some(IfHandler::handle_else(
+                            stream,
+                            context,
+                            can_take && !active,
+                            recipe_dir,
+                        ));
                     }
-                    if_node.else_ = Some(Box::new(else_));
+                    break; // leave the outer loop too, since else block is handled, and else ifeq will recursively reach else too
                 }
-                break; // leave the outer loop too, since else block is handled, and else ifeq will recursively reach else too
+                None => {}
             }
 
             #[cfg(debug_assertions)]
             {
                 line_count += 1;
             }
-            if_node
-                .steps
-                .push(GenericStepHandler::handle(&next_line, stream, context));
-
-            stream.read_line();
-            next_line = stream.peek_next_line().trim().to_string();
+            if_node.steps.extend(IfHandler::handle_step(
+                &next_line,
+                stream,
+                context,
+                active,
+                recipe_dir.as_deref_mut(),
+            ));
         }
 
+        context.conditionals.pop();
+
         debugln!(
             "Duration in ifeq loop: {} Lines => {:?}",
             line_count,
@@ -115,4 +360,129 @@ impl IfHandler {
 
         Box::new(if_node)
     }
+
+    /* Handles lines of a plain `else` branch (the `else` line was just read) till its `endif` */
+    fn handle_else(
+        stream: &mut Stream,
+        context: &mut Context,
+        active: bool,
+        mut recipe_dir: Option<&mut PathBuf>,
+    ) -> Box<ElseASTNode> {
+        let in_recipe = recipe_dir.is_some();
+        let opening = stream.location();
+        let source = stream.source();
+
+        let mut else_ = ElseASTNode {
+            active,
+            steps: Vec::new(),
+        };
+
+        while context.aborted().is_none() {
+            let next_line = stream.peek_next_line().trim_start().to_string();
+            let is_recipe_line = in_recipe && stream.peek_next_line().starts_with('\t');
+            let directive = split_branch_directive(&next_line).filter(|_| !is_recipe_line);
+
+            if !check_balance(stream, context, source, &opening, directive) {
+                break;
+            }
+
+            match directive {
+                Some(("endif", _)) => {
+                    /* endif encountered, if condition ends, so read it in too */
+                    stream.read_line();
+                    break;
+                }
+                Some(_) => {
+                    context.location = stream.peek_location();
+                    context.diagnose(
+                        Severity::Error,
+                        format!("only one 'else' per conditional, 'else' at {}", opening),
+                    );
+                    break;
+                }
+                None => {}
+            }
+
+            else_.steps.extend(IfHandler::handle_step(
+                &next_line,
+                stream,
+                context,
+                active,
+                recipe_dir.as_deref_mut(),
+            ));
+        }
+
+        Box::new(else_)
+    }
+
+    /**
+     * Handles `line` in a branch, which is the next line in `stream`, and reads it in
+     *
+     * Lines of the branch taken are handled same as lines at top level (or as recipe steps, for lines starting with
+     * '\t' in a recipe), while lines of a branch not taken have no side effects, same as make they are kept verbatim,
+     * only nested conditionals are followed (without evaluating them) to find the matching `else`/`endif`
+     *
+     * @returns More than one node only for a rule followed by conditionals not a part of its recipe, see
+     * TargetHandler
+     */
+    fn handle_step(
+        line: &str,
+        stream: &mut Stream,
+        context: &mut Context,
+        active: bool,
+        recipe_dir: Option<&mut PathBuf>,
+    ) -> Vec<Box<dyn ASTNode>> {
+        let is_recipe_line = recipe_dir.is_some() && stream.peek_next_line().starts_with('\t');
+
+        if active {
+            let line = stream.read_line();
+            context.location = stream.location();
+
+            match recipe_dir {
+                Some(current_dir) if is_recipe_line => {
+                    return vec![TargetStepHandler::handle(&line, Some(context), current_dir)];
+                }
+                Some(current_dir) if is_conditional(&line) => {
+                    return vec![IfHandler::handle_conditional(
+                        &line,
+                        stream,
+                        context,
+                        true,
+                        Some(current_dir),
+                    )];
+                }
+                _ => {}
+            }
+
+            let line = line.trim();
+
+            let nodes = GenericStepHandler::handle(line, stream, context);
+
+            if nodes.is_empty() {
+                println!("❗ Unhandled: {}", line);
+
+                return vec![Box::new(UnhandledASTNode {
+                    line: line.to_string(),
+                })];
+            }
+
+            return nodes;
+        }
+
+        /* @note Not read_line, as it doesn't move past `include` lines */
+        stream.read_raw_line();
+
+        let node: Box<dyn ASTNode> = if !is_recipe_line && is_conditional(line) {
+            context.location = stream.location();
+
+            IfHandler::handle_conditional(line, stream, context, false, recipe_dir)
+        } else if !is_recipe_line && DefineHandler::is_define(line) {
+            /* Same as make, a `define` body is skipped as a whole, ie. an `else`/`endif` in it doesn't end the branch */
+            Box::new(DefineHandler::read(line, stream, context))
+        } else {
+            Box::new(TargetGenericStep::new(line.trim().to_string()))
+        };
+
+        vec![node]
+    }
 }
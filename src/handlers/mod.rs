@@ -1,13 +1,22 @@
 mod comment;
+mod define;
 mod executable;
+mod expansion;
 mod export;
 mod ifeq;
 mod target;
+mod variable;
 
 pub use comment::CommentHandler;
+pub use define::DefineHandler;
+pub use expansion::{is_expansion, ExpansionHandler};
 pub use export::ExportHandler;
-pub use ifeq::IfHandler;
-pub use target::{GenericStepHandler, TargetHandler};
+pub use ifeq::{is_conditional, split_branch_directive, IfHandler};
+pub use target::GenericStepHandler;
+pub use variable::{
+    split_assignment, split_target_variable, TargetVariableHandler, UndefineHandler,
+    VariableHandler,
+};
 
 use crate::ast::Context;
 use crate::nodes::*;
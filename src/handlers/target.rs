@@ -1,90 +1,405 @@
 use regex::Regex;
 use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::OnceLock;
 
+use crate::ast::Severity;
 use crate::handlers::*;
+use crate::pattern::match_pattern;
+
+/**
+ * Splits a rule at its first ':' outside of references, into targets and the rest, eg. `$(OBJS): %.o: %.c` into
+ * ("$(OBJS)", " %.o: %.c")
+ *
+ * @returns None if `line` is not a rule, ie. there is no such ':' or no targets before it
+ */
+pub fn split_rule(line: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            ':' if depth == 0 => {
+                return (!line[..i].trim().is_empty()).then(|| (&line[..i], &line[i + 1..]));
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/**
+ * Splits prerequisites of a rule at the first ';' outside of references, eg. `foo.c ; cc foo.c` into
+ * ("foo.c ", Some(" cc foo.c")), what follows the ';' is the first line of the recipe
+ */
+fn split_inline_recipe(dependencies: &str) -> (&str, Option<&str>) {
+    let mut depth = 0;
+
+    for (i, c) in dependencies.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            ';' if depth == 0 => return (&dependencies[..i], Some(&dependencies[i + 1..])),
+            _ => {}
+        }
+    }
+
+    (dependencies, None)
+}
+
+/**
+ * Splits prerequisites into normal and order-only prerequisites, ie. the ones after the first `|` outside of
+ * references, eg. `foo.c | build` into (["foo.c"], ["build"])
+ *
+ * @note Same as make, `|` need not be surrounded by spaces, eg. `foo.c|build`
+ */
+fn split_order_only(dependencies: &str) -> (Vec<String>, Vec<String>) {
+    let mut depth = 0;
+    let mut bar = None;
+
+    for (i, c) in dependencies.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            '|' if depth == 0 => {
+                bar = Some(i);
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    let (normal, order_only) = match bar {
+        Some(bar) => (&dependencies[..bar], &dependencies[bar + 1..]),
+        None => (dependencies, ""),
+    };
+    let words = |text: &str| text.split_whitespace().map(String::from).collect();
+
+    (words(normal), words(order_only))
+}
 
 /* handle \w:*, and read in more lines to complete the target */
 pub struct TargetHandler {}
 
 impl TargetHandler {
-    pub fn handle(line: &str, stream: &mut Stream, context: &mut Context) -> Box<dyn ASTNode> {
+    /**
+     * @returns The rule, followed by conditionals read after its recipe which turned out not to be a part of it, see
+     * TargetHandler::read_recipe
+     */
+    pub fn handle(line: &str, stream: &mut Stream, context: &mut Context) -> Vec<Box<dyn ASTNode>> {
+        let mut following = Vec::new();
+        let rule = TargetHandler::read_rule(line, stream, context, &mut following);
+
+        std::iter::once(rule).chain(following).collect()
+    }
+
+    fn read_rule(
+        line: &str,
+        stream: &mut Stream,
+        context: &mut Context,
+        following: &mut Vec<Box<dyn ASTNode>>,
+    ) -> Box<dyn ASTNode> {
         /* handle \w:*, and read in more lines to complete the target */
-        let (target_name, dependencies) = line
-            .split_once(':')
-            .expect("TargetHandler: Expected ':' after target name");
+        let (target_name, dependencies) =
+            split_rule(line).expect("TargetHandler: Expected ':' after target name");
 
         let target_name = target_name.trim_end(); // remove any leading space after target name
 
-        let mut deps = Vec::new();
-        let dependencies = dependencies.trim();
+        /* `foo.h foo.c &: foo.y`, all the targets are made by a single run of the recipe */
+        let (grouped, target_name) = match target_name.strip_suffix('&') {
+            Some(target_name) => (true, target_name.trim_end()),
+            None => (false, target_name),
+        };
 
-        for dependency in dependencies.split_whitespace() {
-            deps.push(dependency.to_string());
-        }
+        /* Same as make, targets are expanded as soon as the rule is read */
+        let targets: Vec<String> = context
+            .expand(target_name)
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+
+        /* `clean::`, each double-colon rule of a target is independent, with its own recipe */
+        let (kind, dependencies) = match dependencies.strip_prefix(':') {
+            Some(dependencies) => (RuleKind::DoubleColon, dependencies),
+            None => (RuleKind::Single, dependencies),
+        };
+        let (dependencies, inline_recipe) = split_inline_recipe(dependencies);
 
-        let mut target_ast = Target {
-            target_name: target_name.to_string(),
-            defined_in: stream.get_current_file().expect(
-                "TargetHandler: If a target was read, then there must be a file from it was read",
-            ),
-            deps,
-            steps: Vec::new(),
+        /* Static pattern rule, ie. `targets: target-pattern: prereq-patterns` */
+        let static_pattern = split_rule(dependencies).filter(|(pattern, _)| pattern.contains('%'));
+        let dependencies = match static_pattern {
+            Some((_, prerequisites)) => prerequisites.trim(),
+            None => dependencies.trim(),
         };
 
+        let (normal_deps, order_only_deps) = split_order_only(dependencies);
+
+        /* File of the line just read, the stream may already be at next file (eg. if it's the last line of an included file) */
+        let location = stream.location();
+        let defined_in = location.file.clone();
+        let mut steps: Vec<Box<dyn ASTNode>> = Vec::new();
+
         /* To keep track of `cd` statements, will be helpful to get relative locations later in cargo subcommands */
         let mut current_dir = context.root_makefile_dir.clone();
 
-        loop {
-            let line = stream.peek_next_line();
+        if let Some(line) = inline_recipe.filter(|line| !line.trim().is_empty()) {
+            steps.push(TargetStepHandler::handle(
+                line,
+                Some(context),
+                &mut current_dir,
+            ));
+        }
 
-            if !line.starts_with('\t') {
-                break;
-            }
+        TargetHandler::read_recipe(stream, context, &mut current_dir, &mut steps, following);
+
+        if static_pattern.is_none() && targets.iter().any(|target| target.contains('%')) {
+            return Box::new(PatternRule {
+                targets,
+                normal_deps,
+                order_only_deps,
+                terminal: kind == RuleKind::DoubleColon,
+                defined_in,
+                steps,
+            });
+        }
 
-            if !line.trim().is_empty() {
-                target_ast.steps.push(TargetStepHandler::handle(
-                    line,
-                    Some(context),
-                    &mut current_dir,
-                ));
-            }
+        /* Rule for each of the targets, differing only in the name */
+        let rule = Target {
+            target_name: String::new(),
+            normal_deps,
+            order_only_deps,
+            defined_in,
+            kind,
+            stem: None,
+            /* A grouped rule with a single target is same as an ordinary rule */
+            group: match grouped && targets.len() > 1 {
+                true => targets.clone(),
+                false => Vec::new(),
+            },
+            steps: Rc::new(steps),
+        };
 
-            /* read in next line */
-            stream.read_line();
+        if let Some((target_pattern, _)) = static_pattern {
+            /* Back to the rule's line, from the recipe's lines */
+            context.location = location;
+
+            return TargetHandler::static_pattern_rule(
+                target_name,
+                &targets,
+                target_pattern.trim(),
+                &rule,
+                context,
+            );
         }
 
-        Box::new(target_ast)
+        let mut rules: Vec<Target> = targets
+            .iter()
+            .map(|target| rule.with_name(target, None))
+            .collect();
+
+        if rules.len() == 1 {
+            return Box::new(rules.remove(0));
+        }
+
+        /* `a b c: x` is same as a rule for each of a, b and c, sharing the recipe */
+        Box::new(MultiTargetRule {
+            targets: target_name.to_string(),
+            grouped: !rule.group.is_empty(),
+            normal_deps: rule.normal_deps.clone(),
+            order_only_deps: rule.order_only_deps.clone(),
+            rules,
+        })
     }
 }
 
-/* Handles steps and categorizing them into different types */
+/*
+ * Handles a line of the makefile by dispatching it to the handler for its kind, same for lines at top level and
+ * in a taken branch of a conditional
+ */
 pub struct GenericStepHandler {}
 
 impl GenericStepHandler {
-    pub fn handle(line: &str, stream: &mut Stream, context: &mut Context) -> Box<dyn ASTNode> {
-        let line = line.trim();
+    /**
+     * @note `line` must already be read from `stream`, ie. next line of `stream` is the one after `line`, as handlers
+     * like TargetHandler and DefineHandler read in more lines
+     *
+     * @returns Nodes for the line, more than one for a rule followed by conditionals not a part of its recipe (see
+     * TargetHandler::read_recipe), empty if the line is not of any known kind
+     */
+    pub fn handle(line: &str, stream: &mut Stream, context: &mut Context) -> Vec<Box<dyn ASTNode>> {
+        static REGEX_VARIABLE: OnceLock<Regex> = OnceLock::new();
+
+        let regex_variable =
+            REGEX_VARIABLE.get_or_init(|| Regex::new(r"^[^:;=#]+?\s*(:{1,3}|\+|\?|!)?=").unwrap());
 
-        let regex_target = Regex::new(r"\w:.*$").unwrap();
-        let regex_variable = Regex::new(r"\w+ *[\?:\+]?=").unwrap();
+        let line = line.trim();
+        context.location = stream.location();
 
-        if line.starts_with('#') {
-            CommentHandler::handle(line, None)
+        let nodes: Vec<Box<dyn ASTNode>> = if line.starts_with('#') {
+            vec![CommentHandler::handle(line, None)]
+        } else if DefineHandler::is_define(line) {
+            /* NOTE: define must be handled before regex_variable, as `define NAME =` will also match it */
+            vec![DefineHandler::handle(line, stream, context)]
         } else if line.starts_with("export") || line.starts_with("unexport") {
             /* NOTE: export statements must be handled before regex_variable, as it will regex_variable will also match 'export ...=...' */
-            ExportHandler::handle(line, Some(context))
-        } else if regex_target.is_match(line) {
+            vec![ExportHandler::handle(line, Some(context))]
+        } else if line.starts_with("undefine ") || line.starts_with("override undefine ") {
+            vec![UndefineHandler::handle(line, Some(context))]
+        } else if is_expansion(line) {
+            /* NOTE: must be handled before regex_variable, as `$(eval X = 1)` will also match it */
+            vec![ExpansionHandler::handle(line, Some(context))]
+        } else if split_target_variable(line).is_some() {
+            /* NOTE: must be handled before regex_variable and rules, since it matches both */
+            vec![TargetVariableHandler::handle(line, Some(context))]
+        } else if line.starts_with("override ")
+            || line.starts_with("private ")
+            || regex_variable.is_match(line)
+        {
+            /* Modify context */
+            vec![VariableHandler::handle(line, Some(context))]
+        } else if line.starts_with("include") {
+            vec![stream.handle(line, None)]
+        } else if split_rule(line).is_some() {
             TargetHandler::handle(line, stream, context)
+        } else if is_conditional(line) {
+            vec![IfHandler::handle(line, stream, context)]
         } else {
-            Box::new(TargetGenericStep::new(line.to_string()))
+            if let Some((directive, _)) = split_branch_directive(line) {
+                /* Same as make, an `else`/`endif` without a conditional is an error */
+                context.diagnose(Severity::Error, format!("extraneous '{}'", directive));
+            }
+
+            return Vec::new();
+        };
+
+        /* Text generated by `$(eval)`s is parsed next, as if it was written in place of the line just handled */
+        stream.include_evals(context);
+
+        nodes
+    }
+}
+
+impl TargetHandler {
+    /**
+     * Reads recipe lines of the rule just read into `steps`, along with conditionals in between
+     *
+     * Same as make, conditionals are evaluated while reading, and the recipe continues after `endif` unless the branch
+     * taken has something other than recipe lines and comments (eg. a variable or a rule), which ends the rule. Such
+     * a conditional goes to `following` to be handled same as at top level, unless it has recipe lines of the rule
+     * before that. Recipe lines after the `endif` of a conditional which defined a rule are of that rule.
+     */
+    fn read_recipe(
+        stream: &mut Stream,
+        context: &mut Context,
+        current_dir: &mut PathBuf,
+        steps: &mut Vec<Box<dyn ASTNode>>,
+        following: &mut Vec<Box<dyn ASTNode>>,
+    ) {
+        /* Whether the rule has ended, ie. recipe lines are now of a rule defined after it, if any */
+        let mut ended = false;
+
+        loop {
+            let line = stream.peek_next_line();
+            let is_recipe_line = line.starts_with('\t');
+
+            if !is_recipe_line && !is_conditional(line) {
+                break;
+            }
+
+            /* Rule defined last, in the conditional just read */
+            let newer_rule = following
+                .last()
+                .or(steps.last())
+                .is_some_and(|node| node.ends_with_rule());
+
+            if is_recipe_line && ended && !newer_rule {
+                /* Rule has ended, same as make it's an error at top level */
+                break;
+            }
+
+            let step = if is_recipe_line {
+                if line.trim().is_empty() {
+                    stream.read_line();
+                    continue;
+                }
+
+                context.location = stream.peek_location();
+                let step = TargetStepHandler::handle(line, Some(context), current_dir);
+
+                /* read in next line */
+                stream.read_line();
+                step
+            } else {
+                let line = stream.read_line();
+                context.location = stream.location();
+
+                IfHandler::handle_recipe(&line, stream, context, current_dir)
+            };
+
+            let continues_recipe = step.continues_recipe();
+            let has_recipe = !recipe_lines(vec![step.as_ref()]).is_empty();
+
+            let step = match (continues_recipe, newer_rule) {
+                (true, true) => match following.last_mut().or(steps.last_mut()) {
+                    Some(node) => node.push_recipe_step(step).err(),
+                    None => Some(step),
+                },
+                _ => Some(step),
+            };
+
+            match step {
+                Some(step) if !ended && (continues_recipe || has_recipe) => steps.push(step),
+                Some(step) => following.push(step),
+                None => {}
+            }
+
+            ended |= !continues_recipe;
+        }
+    }
+
+    /**
+     * A concrete rule for each of `targets` (expanded `written_targets`) matching `target_pattern`, same as `rule`
+     * except that `%` in each of its prerequisites is replaced by the stem, same as make
+     */
+    fn static_pattern_rule(
+        written_targets: &str,
+        targets: &[String],
+        target_pattern: &str,
+        rule: &Target,
+        context: &mut Context,
+    ) -> Box<dyn ASTNode> {
+        let mut static_rule = StaticPatternRule {
+            targets: written_targets.to_string(),
+            target_pattern: target_pattern.to_string(),
+            normal_deps: rule.normal_deps.clone(),
+            order_only_deps: rule.order_only_deps.clone(),
+            rules: Vec::new(),
+        };
+
+        for target in targets {
+            match match_pattern(target_pattern, target) {
+                Some(stem) => static_rule.rules.push(rule.with_name(target, Some(stem))),
+                None => {
+                    /* Same as make, it is ignored with a warning */
+                    context.diagnose(
+                        Severity::Warning,
+                        format!("target '{}' doesn't match the target pattern", target),
+                    );
+                }
+            }
         }
+
+        Box::new(static_rule)
     }
 }
 
 /* Handles steps and categorizing them into different types */
-struct TargetStepHandler {}
+pub(super) struct TargetStepHandler {}
 
 impl TargetStepHandler {
-    fn handle(
+    pub(super) fn handle(
         line: &str,
         context: Option<&mut Context>,
         current_dir: &mut PathBuf,
@@ -108,7 +423,6 @@ impl TargetStepHandler {
                 .map(|(_, second_part)|
                     /* `second_part` contains the manifest path, just after --manifest-path, ie. first word in `second_part` */
                     second_part
-                        .trim_start()
                         .split_whitespace()
                         .next()
                         .expect("Expected a manifest path (Cargo.toml filepath), after --manifest-path")
@@ -127,10 +441,9 @@ impl TargetStepHandler {
                     .ok()
                     .map(|p| {
                         p.parent()
-                            .expect(&format!(
-                                "ERROR: Manifest path doesn't have a parent: {:?}",
-                                &p
-                            ))
+                            .unwrap_or_else(|| {
+                                panic!("ERROR: Manifest path doesn't have a parent: {:?}", &p)
+                            })
                             .to_str()
                             .expect("Expected UTF-8 encoded filenames")
                             .to_string()
@@ -181,8 +494,7 @@ impl TargetStepHandler {
             if line.starts_with("cd") {
                 let new_path = current_dir.join(
                     line.split_whitespace()
-                        .skip(1)
-                        .next() /* first word after cd statement */
+                        .nth(1) /* first word after cd statement */
                         .expect("Expected path after `cd` statement"),
                 );
 
@@ -209,3 +521,75 @@ impl TargetStepHandler {
         }*/
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::AST;
+    use crate::handlers::GenericStepHandler;
+    use crate::stream::Stream;
+    use std::path::Path;
+
+    /* Recipe lines (unexpanded) of the rules of `goal` in `makefile` */
+    fn recipe(makefile: &str, goal: &str) -> Vec<String> {
+        let mut stream = Stream::from_text("Makefile", makefile);
+        let mut ast = AST::new(Path::new("."));
+
+        while !stream.eof {
+            let line = stream.read_line();
+
+            for node in GenericStepHandler::handle(line.trim(), &mut stream, &mut ast.context) {
+                ast.push(node);
+            }
+        }
+
+        ast.find_rules(goal)
+            .into_iter()
+            .flat_map(|rule| rule.recipe)
+            .collect()
+    }
+
+    #[test]
+    fn recipe_continues_after_conditional() {
+        let makefile = "all:\n\techo a\nifeq ($(DEBUG),1)\n\techo debug\nendif\n\techo done\n";
+        assert_eq!(recipe(makefile, "all"), ["echo a", "echo done"]);
+
+        let makefile = "X = 1\nall:\n\techo a\nifdef X\n\techo x\nendif\n\techo after\n";
+        assert_eq!(recipe(makefile, "all"), ["echo a", "echo x", "echo after"]);
+    }
+
+    #[test]
+    fn recipe_continues_after_empty_or_comment_only_conditional() {
+        let makefile = "X = 1\nall:\n\techo a\nifdef X\nendif\n\techo after\n\
+                        ifdef NOPE\n\techo nope\nelse\n# comment\nendif\n\techo l2\n";
+
+        assert_eq!(recipe(makefile, "all"), ["echo a", "echo after", "echo l2"]);
+    }
+
+    #[test]
+    fn conditional_with_variable_ends_recipe() {
+        let makefile = "all:\n\techo a\nifndef NOPE\nY = 1\nendif\n\techo lost\n";
+
+        assert_eq!(recipe(makefile, "all"), ["echo a"]);
+    }
+
+    #[test]
+    fn recipe_after_conditional_is_of_rule_defined_in_it() {
+        let makefile =
+            "X = 1\nall:\n\techo a\nifdef X\n\techo b\nfoo:\n\techo foo1\nendif\n\techo after\n\
+                        ifdef X\n\techo after2\nendif\nbar:\n\techo bar\n";
+
+        assert_eq!(recipe(makefile, "all"), ["echo a", "echo b"]);
+        assert_eq!(
+            recipe(makefile, "foo"),
+            ["echo foo1", "echo after", "echo after2"]
+        );
+        assert_eq!(recipe(makefile, "bar"), ["echo bar"]);
+    }
+
+    #[test]
+    fn unhandled_line_in_conditional_is_not_a_recipe_line() {
+        let makefile = "all:\n\techo a\nifndef NOPE\nnot a rule\nendif\n\techo lost\n";
+
+        assert_eq!(recipe(makefile, "all"), ["echo a"]);
+    }
+}
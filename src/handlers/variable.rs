@@ -0,0 +1,182 @@
+use super::Handler;
+use crate::ast::{Context, Origin};
+use crate::nodes::{ASTNode, TargetVariableASTNode, UndefineASTNode, VariableASTNode};
+
+/**
+ * Splits an assignment like `CFLAGS += -g` into its name, operator and value
+ * ie. ("CFLAGS", "+=", "-g"), returns None if there is no operator in `expr`
+ */
+pub fn split_assignment(expr: &str) -> Option<(&str, &str, &str)> {
+    let (lhs, value) = expr.split_once('=')?;
+
+    let name = lhs.trim_end_matches([':', '+', '?', '!']);
+    let operator = &expr[name.len()..lhs.len() + 1];
+
+    Some((name.trim(), operator, value.trim_start()))
+}
+
+/**
+ * Splits a target-specific assignment like `kernel: CFLAGS += -g` into the targets and the assignment,
+ * returns None if `line` is not a target-specific assignment
+ */
+pub fn split_target_variable(line: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    let mut colon = None;
+    let mut eq = None;
+
+    /* Only look at ':' and '=' outside of variable references, eg. not the ones in `$(SRCS:.c=.o)` */
+    for (i, c) in line.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            ':' if depth == 0 && colon.is_none() => colon = Some(i),
+            '=' if depth == 0 => {
+                eq = Some(i);
+                break;
+            }
+            /* `target: ; recipe`, anything after ';' is a recipe */
+            ';' if depth == 0 => return None,
+            _ => {}
+        }
+    }
+
+    let (colon, eq) = (colon?, eq?);
+
+    /* `:` in `:=` or `::=` is part of the operator, not a target separator */
+    if line[colon..eq]
+        .trim_matches([':', '+', '?', '!', ' ', '\t'])
+        .is_empty()
+    {
+        return None;
+    }
+
+    Some((line[..colon].trim(), line[colon + 1..].trim()))
+}
+
+/* Parses `[override] [private] NAME op value`, without modifying any context */
+fn parse_variable(line: &str) -> VariableASTNode {
+    let mut line = line.trim();
+    let mut is_override = false;
+    let mut is_private = false;
+
+    /* Modifiers may come in any order, eg. `override private X = y` */
+    loop {
+        if let Some(rest) = line.strip_prefix("override ") {
+            is_override = true;
+            line = rest.trim_start();
+        } else if let Some(rest) = line.strip_prefix("private ") {
+            is_private = true;
+            line = rest.trim_start();
+        } else {
+            break;
+        }
+    }
+
+    let (name, operator, value) = split_assignment(line).unwrap_or_else(|| {
+        panic!(
+            "VariableHandler: Expected var=value expression\n\tLine: {}",
+            line
+        )
+    });
+
+    VariableASTNode {
+        name: name.to_string(),
+        operator: operator.to_string(),
+        value: value.to_string(),
+        is_override,
+        is_private,
+    }
+}
+
+/* handle variable assignments, optionally prefixed with `override` and/or `private` */
+pub struct VariableHandler {}
+
+impl Handler for VariableHandler {
+    fn handle(line: &str, context: Option<&mut Context>) -> Box<dyn ASTNode> {
+        let context = context.expect("VariableHandler requires the context to assign variables");
+
+        let mut variable = parse_variable(line);
+
+        /* Name may be computed, eg. `$(ARCH)_CFLAGS := ...` */
+        variable.name = context.expand(&variable.name);
+
+        let origin = if variable.is_override {
+            Origin::Override
+        } else {
+            Origin::File
+        };
+        context.set(
+            &variable.name,
+            &variable.operator,
+            variable.value.clone(),
+            origin,
+        );
+
+        if variable.is_private {
+            context.make_private(&variable.name);
+        }
+
+        Box::new(variable)
+    }
+}
+
+/* handle target-specific and pattern-specific variables, ie. `targets: VAR op value` */
+pub struct TargetVariableHandler {}
+
+impl Handler for TargetVariableHandler {
+    fn handle(line: &str, context: Option<&mut Context>) -> Box<dyn ASTNode> {
+        let context = context.expect(
+            "TargetVariableHandler requires the context to store target-specific variables",
+        );
+
+        let (targets, assignment) = split_target_variable(line.trim()).unwrap_or_else(|| {
+            panic!(
+                "TargetVariableHandler: Expected \"targets: var=value\" expression\n\tLine: {}",
+                line
+            )
+        });
+
+        /* `export` has no effect on our analysis, so it is just dropped */
+        let assignment = assignment.strip_prefix("export ").unwrap_or(assignment);
+
+        let mut variable = parse_variable(assignment);
+        variable.name = context.expand(&variable.name);
+
+        let targets: Vec<String> = context
+            .expand(targets)
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+
+        for target in &targets {
+            context.set_target_variable(target, variable.clone());
+        }
+
+        Box::new(TargetVariableASTNode { targets, variable })
+    }
+}
+
+/* handle `undefine NAME` and `override undefine NAME` */
+pub struct UndefineHandler {}
+
+impl Handler for UndefineHandler {
+    fn handle(line: &str, context: Option<&mut Context>) -> Box<dyn ASTNode> {
+        let context = context.expect("UndefineHandler requires the context to remove variables");
+
+        let line = line.trim();
+        let (is_override, line) = match line.strip_prefix("override ") {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, line),
+        };
+
+        let name = line
+            .strip_prefix("undefine")
+            .expect("UndefineHandler: Expected line of form \"[override] undefine NAME\"")
+            .trim();
+
+        let name = context.expand(name);
+        context.undefine(&name, is_override);
+
+        Box::new(UndefineASTNode { name, is_override })
+    }
+}
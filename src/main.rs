@@ -1,16 +1,23 @@
-use std::{env, path::Path, process::exit};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::exit,
+};
 use time::{Duration, Instant};
 
-use regex::Regex;
-
 mod ast;
+mod expand;
+mod fs;
 mod handlers;
 mod nodes;
+mod pattern;
 mod stream;
 
-use ast::AST;
+use ast::{Origin, AST};
+use fs::RealFileSystem;
 use handlers::*;
-use stream::Stream;
+use nodes::format_prerequisites;
+use stream::{Location, Stream};
 
 // https://users.rust-lang.org/t/show-value-only-in-debug-mode/43686/2
 macro_rules! debugln {
@@ -28,15 +35,36 @@ macro_rules! debugln {
     };
 }
 
+const USAGE: &str = "Usage: ./makefile-parser [OPTIONS] path/to/Makefile [VAR=value...] [QUERY]
+
+Variables given as VAR=value take precedence over assignments in makefiles, same as in make
+
+Options:
+    --environment              Define variables from the environment
+    --environment-overrides    Same as --environment, but they also override makefile assignments
+    --allow-writes             Let $(file >...) write to the real files, instead of only in memory
+
+Queries:
+    recipe GOAL...      Print expanded recipes of GOALs and their prerequisites
+    which-rule FILE...  Print the rule (explicit or pattern rule) make would use to build FILEs
+    vars                List all variables, with their origin and flavor
+    diagnostics         Print messages of $(error), $(warning) and $(info) calls, with file and line
+    who-sets VAR...     Print every assignment to VARs, with file and line where it was made";
+
 fn main() {
     let start = Instant::now();
     let mut duration_in_if = Duration::new(0, 0);
-    let mut args = env::args().skip(1); // Skip first argument (which is executable path)
+    /* Skip first argument (which is executable path) */
+    let (options, args): (Vec<String>, Vec<String>) =
+        env::args().skip(1).partition(|arg| arg.starts_with("--"));
+    let (overrides, args): (Vec<String>, Vec<String>) =
+        args.into_iter().partition(|arg| arg.contains('='));
+    let mut args = args.into_iter();
 
     let makefile = match args.next() {
         Some(arg) => arg,
         None => {
-            println!("Usage: ./makefile-parser path/to/Makefile");
+            println!("{}", USAGE);
             exit(22 /* EINVAL */);
         }
     };
@@ -47,7 +75,15 @@ fn main() {
         .parent()
         .expect("Failed to get parent directory of given Makefile path");
 
-    std::env::set_current_dir(root_dir).expect("Failed to change directory");
+    /* Absolute, since functions like `$(abspath)` are relative to it, and we change directory below */
+    let root_dir = match root_dir.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => root_dir,
+    }
+    .canonicalize()
+    .expect("Failed to get absolute path of Makefile's directory");
+
+    std::env::set_current_dir(&root_dir).expect("Failed to change directory");
 
     // starting with Makefile in $(cwd)
     let makefile = Path::new(&makefile)
@@ -57,59 +93,216 @@ fn main() {
         .expect("Path must be UTF-8 encoded characters only");
 
     let mut stream = Stream::new(makefile);
-    let mut ast = AST::new(root_dir);
+    let mut ast = AST::new(&root_dir);
 
-    let regex_target = Regex::new(r"\w:.*$").unwrap();
-    let regex_variable = Regex::new(r"\w+ *[\?:\+]?=").unwrap();
+    for option in &options {
+        match option.as_str() {
+            "--environment" => ast.context.import_environment(false),
+            "--environment-overrides" => ast.context.import_environment(true),
+            "--allow-writes" => ast.context.fs = Box::new(RealFileSystem {}),
+            _ => {
+                println!("Unknown option: {}\n{}", option, USAGE);
+                exit(22 /* EINVAL */);
+            }
+        }
+    }
 
-    while stream.eof == false {
+    ast.context.location = Location {
+        file: PathBuf::from("<command line>"),
+        line: 0,
+        from_eval: false,
+    };
+
+    for assignment in &overrides {
+        /* SAFETY: Only arguments containing '=' are in `overrides` */
+        let (var_name, operator, var_value) = split_assignment(assignment).unwrap();
+
+        ast.context.set(
+            var_name,
+            operator,
+            var_value.to_string(),
+            Origin::CommandLine,
+        );
+    }
+
+    /* Same as make, nothing after an `$(error)` is evaluated */
+    while !stream.eof && ast.context.aborted().is_none() {
         let l = stream.read_line();
         let line = l.trim();
 
         // debug!(line);
 
-        if line.starts_with('#') {
-            ast.push(CommentHandler::handle(line, None));
-        } else if line.starts_with("export") || line.starts_with("unexport") {
-            /* NOTE: export statements must be handled before regex_variable, as it will regex_variable will also match 'export ...=...' */
-            ast.nodes
-                .push(ExportHandler::handle(line, Some(&mut ast.context)));
-        } else if regex_variable.is_match(line) {
-            // Modify context
-            /* SAFETY: Regex matched so, it is of the form ARCH?=x86... so split at '=' must return Some() */
-            let (var_name, var_value) = line.split_once('=').unwrap();
-
-            let var_name = var_name.to_string();
-            let var_value = var_value.to_string();
-
-            ast.context.set(var_name, var_value);
-        } else if line.starts_with("include") {
-            ast.push(stream.handle(line, None));
-        } else if regex_target.is_match(line) {
-            let t = TargetHandler::handle(line, &mut stream, &mut ast.context);
-
-            ast.push(t);
-        } else if line.starts_with("ifeq") {
-            #[cfg(debug_assertions)]
-            let start = Instant::now();
-            let ifnode = IfHandler::handle(line, &mut stream, &mut ast.context);
+        #[cfg(debug_assertions)]
+        let start = Instant::now();
 
-            #[cfg(debug_assertions)]
-            {
-                duration_in_if += Instant::now() - start;
-            }
+        let nodes = GenericStepHandler::handle(line, &mut stream, &mut ast.context);
 
-            ast.push(ifnode);
-        } else {
+        if nodes.is_empty() {
             println!("❗ Unhandled: {}", line);
         }
+        for node in nodes {
+            ast.push(node);
+        }
+
+        #[cfg(debug_assertions)]
+        if is_conditional(line) {
+            duration_in_if += Instant::now() - start;
+        }
+    }
+
+    if let Some(error) = ast.context.aborted() {
+        println!(
+            "❗ Evaluation aborted at {}: {}",
+            error.location, error.message
+        );
+    }
+
+    match args.next().as_deref() {
+        None => {
+            let debug_start = Instant::now();
+            println!("{:?}", ast);
+
+            debugln!(
+                "Time taken to print debug      : {:?}",
+                Instant::now() - debug_start
+            );
+        }
+        Some("recipe") => {
+            /* Expanded recipes of each goal, along with the recipes of its prerequisites */
+            let goals: Vec<String> = args.collect();
+
+            for (target, lines) in ast.expand_recipes(&goals) {
+                println!("{}:", target);
+
+                for line in lines {
+                    println!("\t{}", line);
+                }
+            }
+        }
+        Some("which-rule") => {
+            for file in args {
+                let rules = ast.find_rules(&file);
+
+                if rules.is_empty() {
+                    println!("{}: no rule to make it", file);
+                }
+
+                /* More than one only for double-colon rules, each is used independently */
+                for rule in rules {
+                    println!(
+                        "{}: {}",
+                        file,
+                        format_prerequisites(&rule.normal_deps, &rule.order_only_deps)
+                    );
+
+                    match &rule.pattern {
+                        Some(pattern) => println!(
+                            "\tpattern rule: {}\t({})",
+                            pattern,
+                            rule.defined_in.display()
+                        ),
+                        None => println!("\texplicit rule\t({})", rule.defined_in.display()),
+                    }
+
+                    if let Some(stem) = &rule.stem {
+                        println!("\tstem: {}", stem);
+                    }
+
+                    if !rule.group.is_empty() {
+                        println!("\tgroup: {}", rule.group.join(" "));
+                    }
+                }
+            }
+        }
+        Some("vars") => {
+            let width = ast
+                .context
+                .variables()
+                .map(|(name, _)| name.len())
+                .max()
+                .unwrap_or_default();
+
+            println!(
+                "{:width$}  {:20}  {:9}  VALUE",
+                "NAME",
+                "ORIGIN",
+                "FLAVOR",
+                width = width
+            );
+
+            for (name, variable) in ast.context.variables() {
+                println!(
+                    "{:width$}  {:20}  {:9}  {}",
+                    name,
+                    variable.origin.as_str(),
+                    variable.flavor.as_str(),
+                    variable.value.replace('\n', "\\n"),
+                    width = width
+                );
+            }
+        }
+        Some("diagnostics") => {
+            for diagnostic in ast.diagnostics() {
+                println!("{}", diagnostic);
+            }
+        }
+        Some("who-sets") => {
+            /* Every assignment to the given variables, in order, along with where it was made */
+            for var_name in args {
+                match ast.context.get(&var_name) {
+                    Some(variable) => println!(
+                        "{} ({}, {}): {}",
+                        var_name,
+                        variable.origin.as_str(),
+                        variable.flavor.as_str(),
+                        variable.value
+                    ),
+                    None => println!("{} (undefined)", var_name),
+                }
+
+                for assignment in ast.context.history(&var_name) {
+                    let mut line = if assignment.operator == "undefine" {
+                        format!("\t{}: undefine {}", assignment.location, var_name)
+                    } else {
+                        format!(
+                            "\t{}: {}{}{} {} {}",
+                            assignment.location,
+                            if assignment.origin == Origin::Override {
+                                "override "
+                            } else {
+                                ""
+                            },
+                            assignment
+                                .target
+                                .as_ref()
+                                .map(|target| format!("{}: ", target))
+                                .unwrap_or_default(),
+                            var_name,
+                            assignment.operator,
+                            assignment.value
+                        )
+                    };
+
+                    if !assignment.conditionals.is_empty() {
+                        line += &format!("\t[in {}]", assignment.conditionals.join(" > "));
+                    }
+
+                    if !assignment.applied {
+                        line += "\t(ignored)";
+                    }
+
+                    println!("{}", line);
+                }
+            }
+        }
+        Some(query) => {
+            println!("Unknown query: {}\n{}", query, USAGE);
+            exit(22 /* EINVAL */);
+        }
     }
 
-    let debug_start = Instant::now();
-    println!("{:?}", ast);
     let end = Instant::now();
 
-    debugln!("Time taken to print debug      : {:?}", end - debug_start);
     debugln!("Time taken to complete program : {:?}", end - start);
     debugln!("Time taken in ifeq statements  : {:?}", duration_in_if);
 }
@@ -1,18 +1,97 @@
 use std::{
     fmt::{Debug, Formatter, Write},
     path::PathBuf,
+    rc::Rc,
 };
 
 /**
  * Ignore comments for now
  */
-
 pub trait ASTNode
 where
     Self: Debug,
 {
+    /* Nodes nested inside this node, eg. steps in an `ifeq` block */
+    fn children(&self) -> Vec<&dyn ASTNode> {
+        Vec::new()
+    }
+
+    fn as_target(&self) -> Option<&Target> {
+        None
+    }
+
+    fn as_pattern_rule(&self) -> Option<&PatternRule> {
+        None
+    }
+
+    /* The line as written in a recipe, only for nodes which are steps of a Target */
+    fn recipe_line(&self) -> Option<&str> {
+        None
+    }
+
+    /**
+     * Whether this node can be in a recipe without ending it, ie. a recipe line, a comment, or a conditional with only
+     * such nodes in the branch taken, see TargetHandler
+     */
+    fn continues_recipe(&self) -> bool {
+        self.recipe_line().is_some()
+    }
+
+    /* Whether this node is (or ends with) a rule, eg. a conditional whose branch taken defines a rule last */
+    fn ends_with_rule(&self) -> bool {
+        false
+    }
+
+    /**
+     * Appends `step` to the recipe of the rule this node ends with, eg. for recipe lines after the `endif` of a
+     * conditional defining a rule, see TargetHandler
+     *
+     * @returns `step` back if this node doesn't end with a rule
+     */
+    fn push_recipe_step(&mut self, step: Box<dyn ASTNode>) -> Result<(), Box<dyn ASTNode>> {
+        Err(step)
+    }
 }
 
+/* Appends `step` to the recipe `steps`, or to the rule it ends with (eg. defined in a conditional in the recipe) */
+fn push_step(
+    steps: &mut Vec<Box<dyn ASTNode>>,
+    step: Box<dyn ASTNode>,
+) -> Result<(), Box<dyn ASTNode>> {
+    match steps.last_mut() {
+        Some(last) if last.ends_with_rule() => last.push_recipe_step(step),
+        _ => {
+            steps.push(step);
+            Ok(())
+        }
+    }
+}
+
+/* Same as push_step, for the recipe shared by `rules` (see Target::steps) */
+fn push_shared_step(rules: &mut [Target], step: Box<dyn ASTNode>) -> Result<(), Box<dyn ASTNode>> {
+    let mut steps = match rules.first() {
+        Some(rule) => Rc::clone(&rule.steps),
+        None => return Err(step),
+    };
+
+    /* Recipe can only be changed once the rules let go of it */
+    for rule in rules.iter_mut() {
+        rule.steps = Rc::new(Vec::new());
+    }
+
+    let result = match Rc::get_mut(&mut steps) {
+        Some(recipe) => push_step(recipe, step),
+        None => Err(step),
+    };
+
+    for rule in rules.iter_mut() {
+        rule.steps = Rc::clone(&steps);
+    }
+
+    result
+}
+
+#[allow(dead_code)] /* only read through Debug */
 #[derive(Debug)]
 pub struct Comment {
     comment: String,
@@ -26,8 +105,13 @@ impl Comment {
     }
 }
 
-impl ASTNode for Comment {}
+impl ASTNode for Comment {
+    fn continues_recipe(&self) -> bool {
+        true
+    }
+}
 
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 #[derive(Debug)]
 pub enum CargoSubCommand {
     BUILD,
@@ -53,23 +137,289 @@ impl Debug for Cargo {
     }
 }
 
-impl ASTNode for Cargo {}
+impl ASTNode for Cargo {
+    fn recipe_line(&self) -> Option<&str> {
+        Some(&self.complete_cmd)
+    }
+}
+
+/* Whether a rule was written with `:` or `::` */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleKind {
+    /* `foo: bar`, rules of a target are merged, only one of them can have a recipe */
+    Single,
+    /* `foo:: bar`, each rule of a target is independent, with its own prerequisites and recipe */
+    DoubleColon,
+}
 
+#[derive(Clone)]
 pub struct Target {
     pub target_name: String,
-    pub deps: Vec<String>,
+    pub normal_deps: Vec<String>,
+    /* Prerequisites after a `|`, only built before the target, never make it out of date, eg. a directory */
+    pub order_only_deps: Vec<String>,
+    pub defined_in: PathBuf,
+    pub kind: RuleKind,
+    /* Stem, for rules of a static pattern rule */
+    pub stem: Option<String>,
+    /* All targets of a grouped rule (`&:`), made by a single run of the recipe, empty for other rules */
+    pub group: Vec<String>,
+    /* Shared by all rules made from a single rule in the Makefile, eg. by a static pattern rule */
+    pub steps: Rc<Vec<Box<dyn ASTNode>>>,
+}
+
+impl ASTNode for Target {
+    fn children(&self) -> Vec<&dyn ASTNode> {
+        self.steps.iter().map(|step| step.as_ref()).collect()
+    }
+
+    fn as_target(&self) -> Option<&Target> {
+        Some(self)
+    }
+
+    fn ends_with_rule(&self) -> bool {
+        true
+    }
+
+    fn push_recipe_step(&mut self, step: Box<dyn ASTNode>) -> Result<(), Box<dyn ASTNode>> {
+        push_shared_step(std::slice::from_mut(self), step)
+    }
+}
+
+impl Target {
+    /**
+     * Same rule for the target `name`, sharing the recipe
+     *
+     * @param stem If given, `%` in each prerequisite is replaced by it, as in a static pattern rule
+     */
+    pub fn with_name(&self, name: &str, stem: Option<&str>) -> Target {
+        let substitute = |deps: &[String]| -> Vec<String> {
+            match stem {
+                Some(stem) => deps.iter().map(|dep| dep.replacen('%', stem, 1)).collect(),
+                None => deps.to_vec(),
+            }
+        };
+
+        Target {
+            target_name: name.to_string(),
+            normal_deps: substitute(&self.normal_deps),
+            order_only_deps: substitute(&self.order_only_deps),
+            defined_in: self.defined_in.clone(),
+            kind: self.kind,
+            stem: stem.map(String::from),
+            group: self.group.clone(),
+            steps: Rc::clone(&self.steps),
+        }
+    }
+
+    /* Recipe lines as written in the Makefile, ie. unexpanded */
+    pub fn recipe_lines(&self) -> Vec<String> {
+        recipe_lines(self.children())
+    }
+}
+
+/* Recipe lines in `steps` of a rule, including ones in conditionals */
+pub fn recipe_lines(steps: Vec<&dyn ASTNode>) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for step in steps {
+        match step.recipe_line() {
+            Some(line) => lines.push(line.to_string()),
+            /* A rule in a conditional following the recipe, eg. `ifdef X` <newline> `foo:`, has its own recipe */
+            None if step.as_target().is_some() || step.as_pattern_rule().is_some() => {}
+            None => lines.extend(recipe_lines(step.children())),
+        }
+    }
+
+    lines
+}
+
+/* Prerequisites as written in a rule, eg. `foo.c | build` */
+pub fn format_prerequisites(normal: &[String], order_only: &[String]) -> String {
+    match order_only.is_empty() {
+        true => normal.join(" "),
+        false => format!("{} | {}", normal.join(" "), order_only.join(" "))
+            .trim_start()
+            .to_string(),
+    }
+}
+
+/* Rule whose targets are patterns, eg. `%.o: %.c`, used for files without a recipe of their own, see AST::find_rules */
+pub struct PatternRule {
+    /* Target patterns, eg. `%.tab.c %.tab.h: %.y` makes both from a single run of the recipe */
+    pub targets: Vec<String>,
+    pub normal_deps: Vec<String>,
+    pub order_only_deps: Vec<String>,
+    /* `%:: %,v`, used only if its prerequisites exist, see AST::find_pattern_rule */
+    pub terminal: bool,
     pub defined_in: PathBuf,
     pub steps: Vec<Box<dyn ASTNode>>,
 }
 
-impl ASTNode for Target {}
+impl ASTNode for PatternRule {
+    fn children(&self) -> Vec<&dyn ASTNode> {
+        self.steps.iter().map(|step| step.as_ref()).collect()
+    }
+
+    fn as_pattern_rule(&self) -> Option<&PatternRule> {
+        Some(self)
+    }
+
+    fn ends_with_rule(&self) -> bool {
+        true
+    }
+
+    fn push_recipe_step(&mut self, step: Box<dyn ASTNode>) -> Result<(), Box<dyn ASTNode>> {
+        push_step(&mut self.steps, step)
+    }
+}
+
+impl PatternRule {
+    /* Recipe lines as written in the Makefile, ie. unexpanded */
+    pub fn recipe_lines(&self) -> Vec<String> {
+        recipe_lines(self.children())
+    }
+}
+
+impl Debug for PatternRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self.terminal {
+            true => f.write_str(&format!(
+                "Pattern rule: {} (terminal)\n",
+                self.targets.join(" ")
+            ))?,
+            false => f.write_str(&format!("Pattern rule: {}\n", self.targets.join(" ")))?,
+        }
+        f.write_str(&format!("\t\t\tDeps: {:?}\n", self.normal_deps))?;
+
+        if !self.order_only_deps.is_empty() {
+            f.write_str(&format!(
+                "\t\t\tOrder-only deps: {:?}\n",
+                self.order_only_deps
+            ))?;
+        }
+
+        f.write_str(&format!("\t\t\tDefined in: {:?}\n", self.defined_in))?;
+        f.write_str("\t\t\tSteps:\n")?;
+
+        for (i, step) in self.steps.iter().enumerate() {
+            f.write_str(&format!("\t\t\t\t{}: {:?}\n", i, step))?;
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * Rule with more than one target, eg. `a b c: x`, a rule for each target
+ *
+ * @note If `grouped` (`foo.h foo.c &: foo.y`), a single run of the recipe makes all of them, see Target::group
+ */
+pub struct MultiTargetRule {
+    /* As written, ie. unexpanded */
+    pub targets: String,
+    pub grouped: bool,
+    pub normal_deps: Vec<String>,
+    pub order_only_deps: Vec<String>,
+    pub rules: Vec<Target>,
+}
+
+impl ASTNode for MultiTargetRule {
+    fn children(&self) -> Vec<&dyn ASTNode> {
+        self.rules.iter().map(|rule| rule as &dyn ASTNode).collect()
+    }
+
+    fn ends_with_rule(&self) -> bool {
+        true
+    }
+
+    fn push_recipe_step(&mut self, step: Box<dyn ASTNode>) -> Result<(), Box<dyn ASTNode>> {
+        push_shared_step(&mut self.rules, step)
+    }
+}
+
+impl Debug for MultiTargetRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.write_str(&format!(
+            "{}: {}{} {}\n",
+            if self.grouped { "Grouped rule" } else { "Rule" },
+            self.targets,
+            if self.grouped { " &:" } else { ":" },
+            format_prerequisites(&self.normal_deps, &self.order_only_deps)
+        ))?;
+
+        for rule in &self.rules {
+            f.write_str(&format!("\t\t{:?}", rule))?;
+        }
+
+        Ok(())
+    }
+}
+
+/* `targets: target-pattern: prereq-patterns`, eg. `$(OBJS): build/%.o: src/%.c`, a concrete rule for each target */
+pub struct StaticPatternRule {
+    /* As written, ie. unexpanded */
+    pub targets: String,
+    pub target_pattern: String,
+    pub normal_deps: Vec<String>,
+    pub order_only_deps: Vec<String>,
+    pub rules: Vec<Target>,
+}
+
+impl ASTNode for StaticPatternRule {
+    fn children(&self) -> Vec<&dyn ASTNode> {
+        self.rules.iter().map(|rule| rule as &dyn ASTNode).collect()
+    }
+
+    fn ends_with_rule(&self) -> bool {
+        true
+    }
+
+    fn push_recipe_step(&mut self, step: Box<dyn ASTNode>) -> Result<(), Box<dyn ASTNode>> {
+        push_shared_step(&mut self.rules, step)
+    }
+}
+
+impl Debug for StaticPatternRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.write_str(&format!(
+            "Static pattern rule: {}: {}: {}\n",
+            self.targets,
+            self.target_pattern,
+            format_prerequisites(&self.normal_deps, &self.order_only_deps)
+        ))?;
+
+        for rule in &self.rules {
+            f.write_str(&format!("\t\t{:?}", rule))?;
+        }
+
+        Ok(())
+    }
+}
 
 impl Debug for Target {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        f.write_str(&format!("Target: {}\n", self.target_name))?;
-        f.write_str(&format!("\t\t\tDeps: {:?}\n", self.deps))?;
+        match self.kind {
+            RuleKind::Single => f.write_str(&format!("Target: {}\n", self.target_name))?,
+            RuleKind::DoubleColon => {
+                f.write_str(&format!("Target: {} (double-colon)\n", self.target_name))?
+            }
+        }
+        f.write_str(&format!("\t\t\tDeps: {:?}\n", self.normal_deps))?;
+
+        if !self.order_only_deps.is_empty() {
+            f.write_str(&format!(
+                "\t\t\tOrder-only deps: {:?}\n",
+                self.order_only_deps
+            ))?;
+        }
+
+        if !self.group.is_empty() {
+            f.write_str(&format!("\t\t\tGroup: {:?}\n", self.group))?;
+        }
+
         f.write_str(&format!("\t\t\tDefined in: {:?}\n", self.defined_in))?;
-        f.write_str(&format!("\t\t\tSteps:\n"))?;
+        f.write_str("\t\t\tSteps:\n")?;
 
         for (i, step) in self.steps.iter().enumerate() {
             f.write_str(&format!("\t\t\t\t{}: {:?}\n", i, step))?;
@@ -79,6 +429,7 @@ impl Debug for Target {
     }
 }
 
+#[allow(dead_code)] /* only read through Debug */
 #[derive(Debug)]
 pub struct IncludeASTNode {
     pub include_path: String,
@@ -86,6 +437,16 @@ pub struct IncludeASTNode {
 
 impl ASTNode for IncludeASTNode {}
 
+/* A line of no known kind in a conditional's branch taken, kept as is, same as make it ends a recipe */
+#[allow(dead_code)] /* only read through Debug */
+#[derive(Debug)]
+pub struct UnhandledASTNode {
+    pub line: String,
+}
+
+impl ASTNode for UnhandledASTNode {}
+
+#[allow(dead_code)] /* only read through Debug */
 #[derive(Debug)]
 pub struct ExportASTNode {
     name: String,
@@ -100,6 +461,7 @@ impl ExportASTNode {
 
 impl ASTNode for ExportASTNode {}
 
+#[allow(dead_code)] /* only read through Debug */
 #[derive(Debug)]
 pub struct UnExportASTNode {
     name: String,
@@ -113,24 +475,213 @@ impl UnExportASTNode {
 
 impl ASTNode for UnExportASTNode {}
 
+#[derive(Clone)]
+pub struct VariableASTNode {
+    pub name: String,
+    pub operator: String,
+    pub value: String,
+    pub is_override: bool,
+    pub is_private: bool,
+}
+
+impl ASTNode for VariableASTNode {}
+
+impl Debug for VariableASTNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.write_str(&format!(
+            "Variable: {}{}{} {} {}",
+            if self.is_override { "override " } else { "" },
+            if self.is_private { "private " } else { "" },
+            self.name,
+            self.operator,
+            self.value
+        ))
+    }
+}
+
+/* `targets: VAR op value`, where targets may also be patterns like `%.o` */
+pub struct TargetVariableASTNode {
+    pub targets: Vec<String>,
+    pub variable: VariableASTNode,
+}
+
+impl ASTNode for TargetVariableASTNode {}
+
+impl Debug for TargetVariableASTNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.write_str(&format!("Target-specific {:?}\n", self.variable))?;
+        f.write_str(&format!("\t\t\tTargets: {:?}", self.targets))
+    }
+}
+
+#[allow(dead_code)] /* only read through Debug */
+#[derive(Debug)]
+pub struct UndefineASTNode {
+    pub name: String,
+    pub is_override: bool,
+}
+
+impl ASTNode for UndefineASTNode {}
+
+/* A line that is only an expansion, such as `$(eval ...)` or `$(info ...)`, expanded for its side effects */
+#[allow(dead_code)] /* only read through Debug */
+#[derive(Debug)]
+pub struct ExpansionASTNode {
+    pub expression: String,
+}
+
+impl ASTNode for ExpansionASTNode {}
+
+pub struct DefineASTNode {
+    pub name: String,
+    pub operator: String,
+    pub value: String,
+    pub is_override: bool,
+}
+
+impl ASTNode for DefineASTNode {}
+
+impl Debug for DefineASTNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.write_str(&format!(
+            "Define: {}{} {}\n",
+            if self.is_override { "override " } else { "" },
+            self.name,
+            self.operator
+        ))?;
+
+        for line in self.value.lines() {
+            f.write_str(&format!("\t\t\t\t{}\n", line))?;
+        }
+
+        Ok(())
+    }
+}
+
+/* Condition of a conditional directive, arguments are unexpanded */
+#[derive(Clone, PartialEq, Eq)]
+pub enum Condition {
+    /* `ifeq (lhs,rhs)` */
+    Eq(String, String),
+    /* `ifneq (lhs,rhs)` */
+    Ne(String, String),
+    /* `ifdef NAME` */
+    Defined(String),
+    /* `ifndef NAME` */
+    NotDefined(String),
+}
+
+impl Debug for Condition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            Condition::Eq(lhs, rhs) => write!(f, "ifeq ({},{})", lhs, rhs),
+            Condition::Ne(lhs, rhs) => write!(f, "ifneq ({},{})", lhs, rhs),
+            Condition::Defined(name) => write!(f, "ifdef {}", name),
+            Condition::NotDefined(name) => write!(f, "ifndef {}", name),
+        }
+    }
+}
+
 pub struct IfASTNode {
-    pub condition: String,
+    pub condition: Condition,
+    /* Whether this branch was taken, steps of a branch not taken are kept verbatim */
+    pub active: bool,
     pub steps: Vec<Box<dyn ASTNode>>,
     pub elseif_: Option<Box<IfASTNode>>,
     pub else_: Option<Box<ElseASTNode>>,
 }
 
 pub struct ElseASTNode {
+    pub active: bool,
     pub steps: Vec<Box<dyn ASTNode>>,
 }
 
-impl ASTNode for IfASTNode {}
-impl ASTNode for ElseASTNode {}
+/* Only steps of the branch taken are children, since only they are in effect */
+impl ASTNode for IfASTNode {
+    fn children(&self) -> Vec<&dyn ASTNode> {
+        let mut children: Vec<&dyn ASTNode> = Vec::new();
+
+        if self.active {
+            children.extend(self.steps.iter().map(|s| s.as_ref()));
+        }
+
+        if let Some(elseif_) = &self.elseif_ {
+            children.push(elseif_.as_ref());
+        }
+
+        if let Some(else_) = &self.else_ {
+            children.push(else_.as_ref());
+        }
+
+        children
+    }
+
+    fn continues_recipe(&self) -> bool {
+        self.children().iter().all(|step| step.continues_recipe())
+    }
+
+    fn ends_with_rule(&self) -> bool {
+        if self.active {
+            return self.steps.last().is_some_and(|step| step.ends_with_rule());
+        }
+
+        match (&self.elseif_, &self.else_) {
+            (Some(elseif_), _) => elseif_.ends_with_rule(),
+            (None, Some(else_)) => else_.ends_with_rule(),
+            (None, None) => false,
+        }
+    }
+
+    fn push_recipe_step(&mut self, step: Box<dyn ASTNode>) -> Result<(), Box<dyn ASTNode>> {
+        if self.active {
+            return match self.steps.last_mut() {
+                Some(last) => last.push_recipe_step(step),
+                None => Err(step),
+            };
+        }
+
+        match (&mut self.elseif_, &mut self.else_) {
+            (Some(elseif_), _) => elseif_.push_recipe_step(step),
+            (None, Some(else_)) => else_.push_recipe_step(step),
+            (None, None) => Err(step),
+        }
+    }
+}
+
+impl ASTNode for ElseASTNode {
+    fn children(&self) -> Vec<&dyn ASTNode> {
+        match self.active {
+            true => self.steps.iter().map(|step| step.as_ref()).collect(),
+            false => Vec::new(),
+        }
+    }
+
+    fn continues_recipe(&self) -> bool {
+        self.children().iter().all(|step| step.continues_recipe())
+    }
+
+    fn ends_with_rule(&self) -> bool {
+        self.children()
+            .last()
+            .is_some_and(|step| step.ends_with_rule())
+    }
+
+    fn push_recipe_step(&mut self, step: Box<dyn ASTNode>) -> Result<(), Box<dyn ASTNode>> {
+        match (self.active, self.steps.last_mut()) {
+            (true, Some(last)) => last.push_recipe_step(step),
+            _ => Err(step),
+        }
+    }
+}
 
 impl Debug for IfASTNode {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        f.write_str(&format!("If: {}\n", self.condition))?;
-        f.write_str(&format!("\t\t\tSteps:\n"))?;
+        f.write_str(&format!(
+            "If: {:?}{}\n",
+            self.condition,
+            if self.active { "" } else { " [inactive]" }
+        ))?;
+        f.write_str("\t\t\tSteps:\n")?;
 
         for (i, step) in self.steps.iter().enumerate() {
             f.write_str(&format!("\t\t\t\t{}: {:?}\n", i, step))?;
@@ -150,8 +701,11 @@ impl Debug for IfASTNode {
 
 impl Debug for ElseASTNode {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        f.write_str(&format!("Else:\n"))?;
-        f.write_str(&format!("\t\t\tSteps:\n"))?;
+        f.write_str(&format!(
+            "Else:{}\n",
+            if self.active { "" } else { " [inactive]" }
+        ))?;
+        f.write_str("\t\t\tSteps:\n")?;
 
         for (i, step) in self.steps.iter().enumerate() {
             f.write_str(&format!("\t\t\t\t{}: {:?}\n", i, step))?;
@@ -165,7 +719,11 @@ pub struct TargetGenericStep {
     line: String,
 }
 
-impl ASTNode for TargetGenericStep {}
+impl ASTNode for TargetGenericStep {
+    fn recipe_line(&self) -> Option<&str> {
+        Some(&self.line)
+    }
+}
 
 impl Debug for TargetGenericStep {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
@@ -178,14 +736,14 @@ impl Debug for TargetGenericStep {
         match command2 {
             None => {
                 /* Single command */
-                f.write_str(&format!("{}", command1))
+                f.write_str(command1)
             }
             Some(command2) => {
                 /* Multiple commands separated with '&&' */
                 f.write_str(&format!("{} && \\", command1))?;
                 f.write_str(&format!("\n\t\t\t\t{}", command2))?;
 
-                while let Some(command) = it.next() {
+                for command in it {
                     f.write_str(&format!("&& \\\n\t\t\t\t{}", command))?;
                 }
 
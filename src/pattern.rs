@@ -0,0 +1,110 @@
+/**
+ * Matches `name` against a `%` pattern (eg. `%.o`, `build/%.o`), returning the stem ie. the part matched by `%`
+ *
+ * @note A pattern without any `%` only matches itself, with an empty stem
+ */
+pub fn match_pattern<'a>(pattern: &str, name: &'a str) -> Option<&'a str> {
+    match pattern.split_once('%') {
+        Some((prefix, suffix)) => {
+            if name.len() >= prefix.len() + suffix.len()
+                && name.starts_with(prefix)
+                && name.ends_with(suffix)
+            {
+                Some(&name[prefix.len()..name.len() - suffix.len()])
+            } else {
+                None
+            }
+        }
+        None => (pattern == name).then_some(""),
+    }
+}
+
+/**
+ * Matches the file `name` against the target pattern of a pattern rule
+ *
+ * @note Same as make, if `pattern` has no '/', the directory of `name` is ignored while matching, so `%.o` matches
+ * `build/foo.o`, and the directory (eg. `build/`) is returned separately to be added before the prerequisites
+ *
+ * @returns Directory and the stem
+ */
+pub fn match_target_pattern<'a>(pattern: &str, name: &'a str) -> Option<(&'a str, &'a str)> {
+    let (dir, file) = match name.rfind('/') {
+        Some(slash) if !pattern.contains('/') => name.split_at(slash + 1),
+        _ => ("", name),
+    };
+
+    Some((dir, match_pattern(pattern, file)?))
+}
+
+/**
+ * Replaces words in `text` matching `pattern` with `replacement`, where a `%` in `replacement` is
+ * replaced by the stem, same as `$(patsubst pattern,replacement,text)`
+ *
+ * @note Whitespace between words is collapsed to a single space
+ */
+pub fn patsubst(pattern: &str, replacement: &str, text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| match match_pattern(pattern, word) {
+            Some(stem) if pattern.contains('%') => replacement.replacen('%', stem, 1),
+            Some(_) => replacement.to_string(),
+            None => word.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/**
+ * Matches `name` against a shell wildcard pattern, ie. one with `*`, `?` and `[...]` as used by `$(wildcard)`
+ *
+ * @note `name` is a single path component, so `*` doesn't match '/', and a leading '.' must be matched explicitly
+ */
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    glob_match_from(&pattern, &name)
+}
+
+fn glob_match_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| glob_match_from(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && glob_match_from(&pattern[1..], &name[1..]),
+        Some('[') => {
+            let c = match name.first() {
+                Some(c) => *c,
+                None => return false,
+            };
+
+            let negated = matches!(pattern.get(1), Some('!' | '^'));
+            let mut i = if negated { 2 } else { 1 };
+            let mut matched = false;
+
+            /* A ']' right after '[' (or '[!') is part of the set */
+            while i < pattern.len() && (pattern[i] != ']' || i == 1 + negated as usize) {
+                if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+                    matched |= pattern[i] <= c && c <= pattern[i + 2];
+                    i += 3;
+                } else {
+                    matched |= pattern[i] == c;
+                    i += 1;
+                }
+            }
+
+            if i >= pattern.len() {
+                /* No closing ']', so '[' is just a normal character */
+                return c == '[' && glob_match_from(&pattern[1..], &name[1..]);
+            }
+
+            matched != negated && glob_match_from(&pattern[i + 1..], &name[1..])
+        }
+        Some('\\') if pattern.len() > 1 => {
+            name.first() == Some(&pattern[1]) && glob_match_from(&pattern[2..], &name[1..])
+        }
+        Some(p) => name.first() == Some(p) && glob_match_from(&pattern[1..], &name[1..]),
+    }
+}
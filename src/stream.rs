@@ -1,37 +1,128 @@
 use crate::ast::Context;
-use crate::handlers::*;
 use crate::nodes::{ASTNode, IncludeASTNode};
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
 use std::fs::File;
-use std::io::{BufRead, BufReader, Lines};
+use std::io::{BufRead, BufReader, Cursor, Lines};
 use std::path::PathBuf;
 
+/* Where a line was read from, `line` starts from 1, a `line` of 0 means it didn't come from a file (eg. command line) */
+#[derive(Clone, Debug, Default)]
+pub struct Location {
+    pub file: PathBuf,
+    pub line: usize,
+    /* Line was generated by a `$(eval)`, at `file:line` */
+    pub from_eval: bool,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        if self.line == 0 {
+            write!(f, "{}", self.file.display())?;
+        } else {
+            write!(f, "{}:{}", self.file.display(), self.line)?;
+        }
+
+        if self.from_eval {
+            write!(f, " (eval)")?;
+        }
+
+        Ok(())
+    }
+}
+
+/* A file (or text, eg. generated by `$(eval)`) being read, see Stream::lineiterators_stack */
+struct Source {
+    lines: Lines<Box<dyn BufRead>>,
+    filepath: PathBuf,
+    /* Number of lines read from it */
+    line_count: usize,
+    /* Location all its lines are said to be at, for text not read from a file */
+    fixed_location: Option<Location>,
+    /* Identifies the file being read, eg. a file included twice is read as two different sources */
+    id: usize,
+}
+
 /*`Stream` struct is both an ASTNode and a Handler */
 pub struct Stream {
     /* An abstract class over file I/O to support include operations, without reading complete files */
     next_line: String,
-    /* Each element is a pair of Line iterator, and file path */
-    lineiterators_stack: Vec<(Lines<BufReader<File>>, PathBuf)>,
+    next_location: Location,
+    /* Location of the line last returned by self.read_line() */
+    location: Location,
+    /* Source::id of next_line, and of the line last returned by self.read_line() */
+    next_source: usize,
+    source: usize,
+    /* Files being read, the most recently included one at top */
+    lineiterators_stack: Vec<Source>,
+    /* Source::id to be given to the next source */
+    sources_count: usize,
+    /* Lines as they are in the file, read to make next_line, including skipped empty lines, see self.read_verbatim_line() */
+    next_raw_lines: VecDeque<(String, Location)>,
     pub eof: bool,
 }
 
 impl Stream {
     pub fn new(filename: &str) -> Self {
+        let mut stream = Stream::empty();
+
+        stream.include_file(filename); // read in first file
+
+        stream
+    }
+
+    /* Stream of `text`, as if it was the contents of the file `filename` */
+    #[cfg(test)]
+    pub fn from_text(filename: &str, text: &str) -> Self {
+        let mut stream = Stream::empty();
+        let reader: Box<dyn BufRead> = Box::new(Cursor::new(text.to_string()));
+
+        stream.push_source(reader.lines(), PathBuf::from(filename), None);
+        stream.read_in_next_line();
+
+        stream
+    }
+
+    fn empty() -> Self {
         /* Initialise an empty stream */
-        let mut stream = Stream {
+        Stream {
             next_line: String::new(),
+            next_location: Location::default(),
+            location: Location::default(),
+            next_source: 0,
+            source: 0,
             lineiterators_stack: Vec::new(),
+            sources_count: 0,
+            next_raw_lines: VecDeque::new(),
 
             /* By default, we have not yet reached EOF */
             eof: false,
-        };
+        }
+    }
 
-        stream.include_file(filename); // read in first file
+    /* Identifies the file (or `$(eval)` text) the line last returned by self.read_line() was read from */
+    pub fn source(&self) -> usize {
+        self.source
+    }
 
-        stream
+    /* Same as self.source(), for the line returned by self.peek_next_line() */
+    pub fn peek_source(&self) -> usize {
+        self.next_source
     }
 
-    pub fn get_current_file(&self) -> Option<PathBuf> {
-        self.lineiterators_stack.last().map(|pair| pair.1.clone())
+    /* Whether the file `source` is still being read, ie. it hasn't ended */
+    pub fn is_reading(&self, source: usize) -> bool {
+        self.lineiterators_stack.iter().any(|s| s.id == source)
+    }
+
+    /* Location of the line last returned by self.read_line() */
+    pub fn location(&self) -> Location {
+        self.location.clone()
+    }
+
+    /* Location of the line returned by self.peek_next_line() */
+    pub fn peek_location(&self) -> Location {
+        self.next_location.clone()
     }
 
     /**
@@ -41,6 +132,12 @@ impl Stream {
      * In either case, self.next_line will be overriden, or in latter case it will be emptied
      */
     fn read_in_next_line(&mut self) {
+        self.next_raw_lines.clear();
+        self.read_in_joined_line();
+    }
+
+    /* Same as self.read_in_next_line(), but keeps self.next_raw_lines, as it recurses for lines ending with a '\' */
+    fn read_in_joined_line(&mut self) {
         self.next_line = loop {
             if self.lineiterators_stack.is_empty() {
                 /* No more lines to read... so EOF */
@@ -52,34 +149,53 @@ impl Stream {
 
             /* Treated as a stack, the most recently added will be read first */
             /* SAFETY: Just checked above that self.lineiterators_stack is NOT empty... so .last() cannot be None */
-            let line_iter = &mut self.lineiterators_stack.last_mut().unwrap().0;
+            let source = self.lineiterators_stack.last_mut().unwrap();
 
             /* loop until either we find a non-empty line, OR EOF is encountered */
-            match line_iter.next() {
+            match source.lines.next() {
                 Some(res) => {
                     let mut s = res.expect("Failed to read file");
+
+                    source.line_count += 1;
+                    let location = match &source.fixed_location {
+                        Some(location) => location.clone(),
+                        None => Location {
+                            file: source.filepath.clone(),
+                            line: source.line_count,
+                            from_eval: false,
+                        },
+                    };
+                    let source = source.id;
+                    self.next_raw_lines.push_back((s.clone(), location.clone()));
+
                     if !s.trim().is_empty() {
                         /* If this line ends with a '\', read in the next line and join it, this may recurse deep depending on how many consecutive lines end with a '\' */
                         if !s.trim_start().starts_with('#') && s.ends_with('\\') {
                             s.pop(); // remove the '\' character
-                            self.read_in_next_line();
+                            self.read_in_joined_line();
 
                             if self.peek_next_line().trim().starts_with('#') {
                                 #[cfg(debug_assertions)]
                                 println!("⚠ Wierd syntax: Ignoring a comment line, since previous line ends at '\\'. Line: {}", self.peek_next_line());
 
                                 /* re-read next line, skipping the current one */
-                                self.read_in_next_line();
+                                self.read_in_joined_line();
                             }
 
                             /* If the next line that was read is empty... that means end this recursion, next line was empty.
                              * This check is needed, because self.read_in_next_line by default, ignores empty lines, so an '\' followed by an empty line will be skipped and read in some other next lines, while actually this line should logically end with the empty line also */
                             if self.peek_next_line().trim().is_empty() {
+                                self.next_location = location;
+                                self.next_source = source;
                                 break s;
                             }
 
                             s += self.peek_next_line().trim();
                         }
+
+                        /* A line joined with next lines, is said to be at its first line */
+                        self.next_location = location;
+                        self.next_source = source;
                         break s;
                     }
                 }
@@ -94,6 +210,8 @@ impl Stream {
     pub fn read_line(&mut self) -> String {
         /* Cannot move out of mutable borrowed values... ie. cannot move self.next_line, while self is a reference (mutable or immutable) */
         let old_line = self.next_line.clone();
+        self.location = self.next_location.clone();
+        self.source = self.next_source;
 
         /*
          * @note: In case current statement is a include, don't read in next line from current file... the next line should be of the included file... so self.read_in_next_line() must be called inside Stream::include_file
@@ -105,20 +223,71 @@ impl Stream {
         old_line
     }
 
+    /* @note Unlike self.read_line(), this always moves to the next line, even if current one is an `include`, useful when lines are to be stored verbatim (eg. `define` bodies) */
+    pub fn read_raw_line(&mut self) -> String {
+        let old_line = self.next_line.clone();
+        self.location = self.next_location.clone();
+        self.source = self.next_source;
+
+        self.read_in_next_line();
+
+        old_line
+    }
+
+    /**
+     * Reads the next line exactly as it is in the file, ie. empty lines are not skipped and lines ending with a '\'
+     * are not joined, useful when lines are to be stored verbatim (eg. `define` bodies)
+     *
+     * @note Once started, verbatim lines must be read till all lines making self.peek_next_line() are read, before
+     * going back to self.read_line()
+     */
+    pub fn read_verbatim_line(&mut self) -> String {
+        match self.next_raw_lines.pop_front() {
+            Some((line, location)) => {
+                self.location = location;
+                self.source = self.next_source;
+
+                /* All lines making next_line are read, move on to the lines after them */
+                if self.next_raw_lines.is_empty() {
+                    self.read_in_next_line();
+                }
+
+                line
+            }
+            None => self.read_raw_line(),
+        }
+    }
+
     /* @note It will return same string as self.read_line(), just that the self.next_line will not change after this call... so this is kind of read-only no-updation version of self.read_line */
     pub fn peek_next_line(&self) -> &str {
         &self.next_line
     }
 
+    fn push_source(
+        &mut self,
+        lines: Lines<Box<dyn BufRead>>,
+        filepath: PathBuf,
+        fixed_location: Option<Location>,
+    ) {
+        self.sources_count += 1;
+
+        self.lineiterators_stack.push(Source {
+            lines,
+            filepath,
+            line_count: 0,
+            fixed_location,
+            id: self.sources_count,
+        });
+    }
+
     /** @note: After this, the given filepath will be at top of files/line_iterators stack, so it will be the file to be read in next self.read_in_next_lines() calls*/
     fn include_file(&mut self, filepath: &str) {
         let file = File::open(filepath).expect("Failed to open file");
-        let reader = BufReader::new(file);
+        let reader: Box<dyn BufRead> = Box::new(BufReader::new(file));
 
         let line_iter = reader.lines();
 
-        self.lineiterators_stack
-            .push((line_iter, PathBuf::from(filepath)));
+        self.push_source(line_iter, PathBuf::from(filepath), None);
 
         // Read in next line from the newly included file
         self.read_in_next_line()
@@ -126,15 +295,67 @@ impl Stream {
 }
 
 impl Stream {
+    /**
+     * Makes `text` the next lines to be read, as if it was written at `location`, used for `$(eval)`
+     *
+     * @note Line that was going to be read next, is read after all lines of `text`
+     */
+    pub fn include_text(&mut self, text: String, location: Location) {
+        if !self.eof {
+            /* next_line was already read from its file, so it has to be put back on the stack to be read after `text` */
+            let pending: Box<dyn BufRead> = Box::new(Cursor::new(self.next_line.clone()));
+            let pending_location = self.next_location.clone();
+
+            /* Still the same file as before, eg. an `endif` closing a conditional opened before `$(eval)` */
+            self.lineiterators_stack.push(Source {
+                lines: pending.lines(),
+                filepath: pending_location.file.clone(),
+                line_count: 0,
+                fixed_location: Some(pending_location),
+                id: self.next_source,
+            });
+        }
+
+        let reader: Box<dyn BufRead> = Box::new(Cursor::new(text));
+
+        self.push_source(
+            reader.lines(),
+            location.file.clone(),
+            Some(Location {
+                from_eval: true,
+                ..location
+            }),
+        );
+
+        self.eof = false;
+        self.read_in_next_line();
+    }
+
+    /* Includes text generated by `$(eval)`s in the line just handled, see Stream::include_text */
+    pub fn include_evals(&mut self, context: &mut Context) {
+        let evals = context.take_evals();
+
+        if let Some((_, location)) = evals.first() {
+            let location = location.clone();
+            let text = evals
+                .into_iter()
+                .map(|(text, _)| text)
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            self.include_text(text, location);
+        }
+    }
+
     pub fn handle(&mut self, line: &str, _c: Option<&mut Context>) -> Box<dyn ASTNode> {
         let tokens: Vec<&str> = line.split_whitespace().collect();
 
-        if tokens.len() < 2 || !tokens[0].contains(&"include") {
+        if tokens.len() < 2 || !tokens[0].contains("include") {
             panic!("Stream handler handles input strings of form \"include \"filename\"\"");
         }
 
         if tokens.len() > 2 {
-            let mut it = tokens.iter().skip(2);
+            let it = tokens.iter().skip(2);
             println!(
                 "❗ Ignoring tokens after include statement: {:?}",
                 it.collect::<Vec<&&str>>()